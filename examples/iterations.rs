@@ -9,7 +9,9 @@ fn get_iterations() -> usize {
 fn main() {
     let iterations = get_iterations();
     eprintln!("Hello from `iterations.rs`. Running {iterations} iterations!!!");
+    depict::start();
     run(iterations);
+    depict::stop();
 }
 
 fn run(n: usize) {
@@ -1,15 +1,48 @@
+// For `dladdr`
+#define _GNU_SOURCE
+
 #include "QBDIPreload.h"
 
 #include <dlfcn.h>
+#include <pthread.h>
+#include <stdatomic.h>
 #include <stdint.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
-#include <inttypes.h>
+#include <unistd.h>
 
 QBDIPRELOAD_INIT;
 
+/// From `DEPICT_QBDI_OUTPUT`. Only set in the benchmarked process, as processes it starts inherit
+/// the environment (and the preload) and would overwrite its results
+static char *output_path = NULL;
+static pid_t output_pid = 0;
+
+/// With `--roi` only count between `depict_start` and `depict_stop`. Read once, as `getenv` is
+/// too slow to call for every instruction
+static bool region_of_interest = false;
+/// Shared by all threads: `depict_start` and `depict_stop` on any thread start and stop counting
+/// on every thread. Relaxed, as counting is not ordered with anything else
+static atomic_bool counting = true;
+
 int qbdipreload_on_start(void *main) { 
+	// The first instrumented process claims the output. `exec` keeps the process id, so wrappers
+	// such as `sh -c` still work
+	char pid[32];
+	snprintf(pid, sizeof(pid), "%d", (int)getpid());
+	const char *owner = getenv("DEPICT_QBDI_PID");
+	if (owner == NULL) {
+		setenv("DEPICT_QBDI_PID", pid, 1);
+		owner = pid;
+	}
+	const char *path = getenv("DEPICT_QBDI_OUTPUT");
+	if (strcmp(owner, pid) == 0 && path != NULL) {
+		output_path = strdup(path);
+		output_pid = getpid();
+	}
+	region_of_interest = getenv("DEPICT_REGION_OF_INTEREST") != NULL;
+	atomic_store_explicit(&counting, !region_of_interest, memory_order_relaxed);
 	return QBDIPRELOAD_NOT_HANDLED; 
 }
 
@@ -29,29 +62,30 @@ int qbdipreload_on_main(int argc, char **argv) {
 typedef struct Entry {
 	char *symbol;
 	char *kind;
+	/// -1 for the whole program
+	int region;
 	uint64_t count;
 	struct Entry *next;
 } Entry;
 
 #define HASH_SIZE 4096
 
-static Entry *table[HASH_SIZE];
-
 /// Fowler–Noll–Vo hash function I found somewhere
-static uint64_t hash_pair(const char *a, const char *b) {
+static uint64_t hash_pair(const char *a, const char *b, int region) {
 	uint64_t h = 1469598103934665603ULL;
 	for (; *a; a++) h = (h ^ *a) * 1099511628211ULL;
 	for (; *b; b++) h = (h ^ *b) * 1099511628211ULL;
+	h = (h ^ (uint64_t)region) * 1099511628211ULL;
 	return h % HASH_SIZE;
 }
 
 /// increment value in the table.
-static void increment(const char *sym, const char *kind) {
-	uint64_t h = hash_pair(sym, kind);
+static void increment(Entry **table, const char *sym, const char *kind, int region) {
+	uint64_t h = hash_pair(sym, kind, region);
 	Entry *e = table[h];
 	while (e) {
 		// if existing entry then increment that value
-		if (strcmp(e->symbol, sym) == 0 && strcmp(e->kind, kind) == 0) {
+		if (e->region == region && strcmp(e->symbol, sym) == 0 && strcmp(e->kind, kind) == 0) {
 			e->count++;
 			return;
 		}
@@ -61,15 +95,293 @@ static void increment(const char *sym, const char *kind) {
 	e = calloc(1, sizeof(Entry));
 	e->symbol = strdup(sym);
 	e->kind   = strdup(kind);
+	e->region = region;
 	e->count  = 1;
 	e->next   = table[h];
 	table[h]  = e;
 }
 
+/// From `depict::region`. Nested regions have a parent
+typedef struct Region {
+	char *name;
+	int parent;
+} Region;
+
+/// Shared by all threads, so ids are the same in each
+static Region *regions = NULL;
+static int region_count = 0;
+static pthread_mutex_t regions_lock = PTHREAD_MUTEX_INITIALIZER;
+
+/// Returns the region (by id) entered from `parent`
+static int enter_region(int parent, const char *name, size_t length) {
+	pthread_mutex_lock(&regions_lock);
+	for (int i = 0; i < region_count; i++) {
+		if (regions[i].parent == parent && strlen(regions[i].name) == length && strncmp(regions[i].name, name, length) == 0) {
+			pthread_mutex_unlock(&regions_lock);
+			return i;
+		}
+	}
+	regions = realloc(regions, (region_count + 1) * sizeof(Region));
+	regions[region_count].name = strndup(name, length);
+	regions[region_count].parent = parent;
+	int region = region_count++;
+	pthread_mutex_unlock(&regions_lock);
+	return region;
+}
+
+static int region_parent(int region) {
+	pthread_mutex_lock(&regions_lock);
+	int parent = regions[region].parent;
+	pthread_mutex_unlock(&regions_lock);
+	return parent;
+}
+
+/// Kinds with inclusive counts. Anything else is only in `total`
+static const char *inclusive_kinds[] = {
+	"branch", "call", "return", "compare", "mem_read", "mem_write", "arithmetic", "logic",
+};
+#define INCLUSIVE_KINDS (sizeof(inclusive_kinds) / sizeof(inclusive_kinds[0]))
+/// Index of `total` in the counters
+#define INCLUSIVE_TOTAL INCLUSIVE_KINDS
+
+typedef struct InclusiveEntry {
+	char *symbol;
+	/// Number of frames for the symbol on the stack, so recursive calls are only counted once
+	int depth;
+	uint64_t counts[INCLUSIVE_KINDS + 1];
+	struct InclusiveEntry *next;
+} InclusiveEntry;
+
+static InclusiveEntry *inclusive_entry(InclusiveEntry **inclusive_table, const char *sym) {
+	uint64_t h = hash_pair(sym, "", -1);
+	InclusiveEntry *e = inclusive_table[h];
+	while (e) {
+		if (strcmp(e->symbol, sym) == 0) return e;
+		e = e->next;
+	}
+	e = calloc(1, sizeof(InclusiveEntry));
+	e->symbol = strdup(sym);
+	e->next = inclusive_table[h];
+	inclusive_table[h] = e;
+	return e;
+}
+
+/// Calls from one function to another
+typedef struct CallEdge {
+	InclusiveEntry *caller;
+	InclusiveEntry *callee;
+	uint64_t calls;
+	/// Instructions executed in the callee (and its callees) for these calls
+	uint64_t inclusive;
+	struct CallEdge *next;
+} CallEdge;
+
+static CallEdge *call_edge(CallEdge **call_table, InclusiveEntry *caller, InclusiveEntry *callee) {
+	uint64_t h = hash_pair(caller->symbol, callee->symbol, -1);
+	CallEdge *e = call_table[h];
+	while (e) {
+		if (e->caller == caller && e->callee == callee) return e;
+		e = e->next;
+	}
+	e = calloc(1, sizeof(CallEdge));
+	e->caller = caller;
+	e->callee = callee;
+	e->next = call_table[h];
+	call_table[h] = e;
+	return e;
+}
+
+/// Calling context tree, for stacks. Parents are always created before their children
+typedef struct Context {
+	int parent;
+	InclusiveEntry *entry;
+	uint64_t counts[INCLUSIVE_KINDS + 1];
+} Context;
+
+typedef struct ContextKey {
+	int parent;
+	InclusiveEntry *entry;
+	int context;
+	struct ContextKey *next;
+} ContextKey;
+
+/// Functions are pushed on the instruction after a call and popped on return. NULL entries are
+/// for code without symbols
+typedef struct Frame {
+	InclusiveEntry *entry;
+	/// -1 for code without symbols
+	int context;
+	/// NULL for the first frame or calls from or to code without symbols
+	CallEdge *edge;
+	uint64_t entered_at[INCLUSIVE_KINDS + 1];
+} Frame;
+
+/// Instructions executed at each address, for source lines
+typedef struct AddressEntry {
+	rword address;
+	char *symbol;
+	char *kind;
+	uint64_t count;
+	struct AddressEntry *next;
+} AddressEntry;
+
+/// Everything counted by a thread. Threads only touch their own, so counting does not need locks
+typedef struct Thread {
+	/// In order of creation. The main thread is 0
+	int index;
+	Entry *table[HASH_SIZE];
+	/// Counters of everything executed so far, snapshotted when functions are entered
+	uint64_t executed[INCLUSIVE_KINDS + 1];
+	InclusiveEntry *inclusive_table[HASH_SIZE];
+	CallEdge *call_table[HASH_SIZE];
+	Context *contexts;
+	int context_count;
+	int context_capacity;
+	ContextKey *context_table[HASH_SIZE];
+	Frame *frames;
+	size_t frame_count;
+	size_t frame_capacity;
+	/// The first function is entered as if called
+	bool after_call;
+	int current_region;
+	AddressEntry *address_table[HASH_SIZE];
+	struct Thread *next;
+} Thread;
+
+/// All threads, in order of creation, for printing on exit
+static Thread *threads = NULL;
+static Thread **last_thread = &threads;
+static int thread_count = 0;
+static pthread_mutex_t threads_lock = PTHREAD_MUTEX_INITIALIZER;
+
+static __thread Thread *current = NULL;
+
+static Thread *current_thread(void) {
+	if (current == NULL) {
+		current = calloc(1, sizeof(Thread));
+		current->after_call = true;
+		current->current_region = -1;
+		pthread_mutex_lock(&threads_lock);
+		current->index = thread_count++;
+		*last_thread = current;
+		last_thread = &current->next;
+		pthread_mutex_unlock(&threads_lock);
+	}
+	return current;
+}
+
+static int child_context(Thread *t, int parent, InclusiveEntry *entry) {
+	uint64_t h = hash_pair(entry->symbol, "", parent);
+	ContextKey *e = t->context_table[h];
+	while (e) {
+		if (e->parent == parent && e->entry == entry) return e->context;
+		e = e->next;
+	}
+	if (t->context_count == t->context_capacity) {
+		t->context_capacity = t->context_capacity ? t->context_capacity * 2 : 256;
+		t->contexts = realloc(t->contexts, t->context_capacity * sizeof(Context));
+	}
+	t->contexts[t->context_count] = (Context){ .parent = parent, .entry = entry };
+	e = calloc(1, sizeof(ContextKey));
+	e->parent = parent;
+	e->entry = entry;
+	e->context = t->context_count++;
+	e->next = t->context_table[h];
+	t->context_table[h] = e;
+	return e->context;
+}
+
+static void push_frame(Thread *t, const char *sym) {
+	if (t->frame_count == t->frame_capacity) {
+		t->frame_capacity = t->frame_capacity ? t->frame_capacity * 2 : 64;
+		t->frames = realloc(t->frames, t->frame_capacity * sizeof(Frame));
+	}
+	InclusiveEntry *caller = t->frame_count > 0 ? t->frames[t->frame_count - 1].entry : NULL;
+	int parent_context = t->frame_count > 0 ? t->frames[t->frame_count - 1].context : -1;
+	Frame *frame = &t->frames[t->frame_count++];
+	frame->entry = sym != NULL ? inclusive_entry(t->inclusive_table, sym) : NULL;
+	frame->context = frame->entry != NULL ? child_context(t, parent_context, frame->entry) : parent_context;
+	frame->edge = NULL;
+	if (frame->entry != NULL) {
+		frame->entry->depth++;
+		if (caller != NULL) {
+			frame->edge = call_edge(t->call_table, caller, frame->entry);
+			frame->edge->calls++;
+		}
+	}
+	memcpy(frame->entered_at, t->executed, sizeof(t->executed));
+}
+
+static void pop_frame(Thread *t) {
+	// Unbalanced (for example `longjmp`)
+	if (t->frame_count == 0) return;
+	Frame *frame = &t->frames[--t->frame_count];
+	// Per call, so recursive calls are counted for each edge
+	if (frame->edge != NULL) {
+		frame->edge->inclusive += t->executed[INCLUSIVE_TOTAL] - frame->entered_at[INCLUSIVE_TOTAL];
+	}
+	if (frame->entry != NULL && --frame->entry->depth == 0) {
+		for (size_t i = 0; i <= INCLUSIVE_KINDS; i++) {
+			frame->entry->counts[i] += t->executed[i] - frame->entered_at[i];
+		}
+	}
+}
+
+/// Adds an instruction executed in `sym` to the current stack. `kind` is from `record_executed`
+static void record_stack(Thread *t, const char *sym, int kind) {
+	int context = t->frame_count > 0 ? t->frames[t->frame_count - 1].context : -1;
+	InclusiveEntry *top = t->frame_count > 0 ? t->frames[t->frame_count - 1].entry : NULL;
+	if (top == NULL || strcmp(top->symbol, sym) != 0) {
+		// Entered by a jump
+		context = child_context(t, context, inclusive_entry(t->inclusive_table, sym));
+	}
+	t->contexts[context].counts[INCLUSIVE_TOTAL]++;
+	if (kind != -1) t->contexts[context].counts[kind]++;
+}
+
+/// Returns the index of the kind, or -1 if it is only counted in `total`
+static int record_executed(Thread *t, const char *kind) {
+	t->executed[INCLUSIVE_TOTAL]++;
+	for (size_t i = 0; i < INCLUSIVE_KINDS; i++) {
+		if (strcmp(inclusive_kinds[i], kind) == 0) {
+			t->executed[i]++;
+			return (int)i;
+		}
+	}
+	return -1;
+}
+
+/// An address always has the same symbol and kind
+static void record_address(AddressEntry **address_table, rword address, const char *sym, const char *kind) {
+	uint64_t h = ((uint64_t)address * 11400714819323198485ULL) % HASH_SIZE;
+	AddressEntry *e = address_table[h];
+	while (e) {
+		if (e->address == address) {
+			e->count++;
+			return;
+		}
+		e = e->next;
+	}
+
+	e = calloc(1, sizeof(AddressEntry));
+	e->address = address;
+	e->symbol  = strdup(sym);
+	e->kind    = strdup(kind);
+	e->count   = 1;
+	e->next    = address_table[h];
+	address_table[h] = e;
+}
+
 bool starts_with(const char *str, const char *pre) {
     return strncmp(str, pre, strlen(pre)) == 0;
 }
 
+/// Mach-O symbols have a leading underscore
+static bool is_symbol(const char *sym, const char *name) {
+	if (sym[0] == '_') sym++;
+	return strcmp(sym, name) == 0;
+}
+
 static const char *classify(const InstAnalysis *ia) {
 	// return ia->mnemonic;
 	if (ia->isBranch) return "branch";
@@ -85,33 +397,266 @@ static const char *classify(const InstAnalysis *ia) {
 	// return "other";
 }
 
+/// Markers and counts for the instruction
+static void count_instruction(Thread *t, const InstAnalysis *ia, GPRState *gprState) {
+	if (ia->symbolName != NULL) {
+		const char *sym  = ia->symbolName;
+		if (is_symbol(sym, "depict_start")) {
+			if (ia->symbolOffset == 0) atomic_store_explicit(&counting, true, memory_order_relaxed);
+			return;
+		} else if (is_symbol(sym, "depict_stop")) {
+			if (ia->symbolOffset == 0 && region_of_interest) atomic_store_explicit(&counting, false, memory_order_relaxed);
+			return;
+		} else if (is_symbol(sym, "depict_region_enter")) {
+			// `extern "C" fn(name: *const u8, length: usize)`
+#if defined(QBDI_ARCH_AARCH64)
+			if (ia->symbolOffset == 0) t->current_region = enter_region(t->current_region, (const char *)gprState->x0, (size_t)gprState->x1);
+#else
+			if (ia->symbolOffset == 0) t->current_region = enter_region(t->current_region, (const char *)gprState->rdi, (size_t)gprState->rsi);
+#endif
+			return;
+		} else if (is_symbol(sym, "depict_region_exit")) {
+			if (ia->symbolOffset == 0 && t->current_region != -1) t->current_region = region_parent(t->current_region);
+			return;
+		}
+		if (atomic_load_explicit(&counting, memory_order_relaxed)) {
+			const char *kind = classify(ia);
+			record_stack(t, sym, record_executed(t, kind));
+			record_address(t->address_table, ia->address, sym, kind);
+			increment(t->table, sym, kind, -1);
+			for (int region = t->current_region; region != -1; region = region_parent(region)) {
+				increment(t->table, sym, kind, region);
+			}
+		}
+	}
+}
+
 static VMAction onInstruction(VMInstanceRef vm, GPRState *gprState, FPRState *fprState, void *data) {
 	const int flag = QBDI_ANALYSIS_INSTRUCTION | QBDI_ANALYSIS_OPERANDS | QBDI_ANALYSIS_SYMBOL;
 	const InstAnalysis *ia = qbdi_getInstAnalysis(vm, flag);
+	Thread *t = current_thread();
 
-	if (ia->symbolName != NULL) {
-		const char *sym  = ia->symbolName;
-		const char *kind = classify(ia);
-		increment(sym, kind);
+	if (t->after_call) {
+		push_frame(t, ia->symbolName);
+		t->after_call = false;
 	}
 
+	count_instruction(t, ia, gprState);
+
+	// After counting, so the return is counted in the function
+	if (ia->isReturn) pop_frame(t);
+	t->after_call = ia->isCall;
+
 	return QBDI_CONTINUE;
 }
 
+/// Threads created natively (see `create_thread`) start in their own VM, as a VM can only be used
+/// by one thread
+typedef struct ThreadStart {
+	void *(*routine)(void *);
+	void *arg;
+} ThreadStart;
+
+#define THREAD_STACK_SIZE (1 << 20)
+
+static void *instrumented_start(void *data) {
+	ThreadStart start = *(ThreadStart *)data;
+	free(data);
+
+	VMInstanceRef vm;
+	qbdi_initVM(&vm, NULL, NULL, 0);
+	qbdi_instrumentAllExecutableMaps(vm);
+	// Including `pthread_create` from this library would clone inside the VM
+	qbdi_removeInstrumentedModuleFromAddr(vm, (rword)&instrumented_start);
+	uint8_t *stack = NULL;
+	qbdi_allocateVirtualStack(qbdi_getGPRState(vm), THREAD_STACK_SIZE, &stack);
+	qbdi_addCodeCB(vm, QBDI_PREINST, onInstruction, NULL, 0);
+
+	rword result = 0;
+	qbdi_call(vm, &result, (rword)start.routine, 1, (rword)start.arg);
+
+	qbdi_alignedFree(stack);
+	qbdi_terminateVM(vm);
+	return (void *)result;
+}
+
+typedef int (*PthreadCreate)(pthread_t *, const pthread_attr_t *, void *(*)(void *), void *);
+
+static int create_thread(PthreadCreate real, pthread_t *thread, const pthread_attr_t *attr, void *(*routine)(void *), void *arg) {
+	ThreadStart *start = malloc(sizeof(ThreadStart));
+	start->routine = routine;
+	start->arg = arg;
+	return real(thread, attr, instrumented_start, start);
+}
+
+#if defined(__APPLE__)
+static int depict_pthread_create(pthread_t *thread, const pthread_attr_t *attr, void *(*routine)(void *), void *arg) {
+	return create_thread(pthread_create, thread, attr, routine, arg);
+}
+
+__attribute__((used, section("__DATA,__interpose"))) static const struct {
+	const void *replacement;
+	const void *original;
+} interpose_pthread_create = { (const void *)depict_pthread_create, (const void *)pthread_create };
+#else
+int pthread_create(pthread_t *thread, const pthread_attr_t *attr, void *(*routine)(void *), void *arg) {
+	static PthreadCreate real = NULL;
+	if (real == NULL) real = (PthreadCreate)dlsym(RTLD_NEXT, "pthread_create");
+	return create_thread(real, thread, attr, routine, arg);
+}
+#endif
+
 int qbdipreload_on_run(VMInstanceRef vm, rword start, rword stop) {
+	qbdi_removeInstrumentedModuleFromAddr(vm, (rword)&instrumented_start);
 	qbdi_addCodeCB(vm, QBDI_PREINST, onInstruction, NULL, 0);
 	qbdi_run(vm, start, stop);
 	return QBDIPRELOAD_NO_ERROR;
 }
 
-int qbdipreload_on_exit(int status) { 
+/// Record tags, matching `src/tools/qbdi.rs`
+enum Tag {
+	TAG_COUNT = 1,
+	TAG_THREAD = 2,
+	TAG_INCLUSIVE = 3,
+	TAG_STACK = 4,
+	TAG_STACK_KIND = 5,
+	TAG_CALL = 6,
+	TAG_ADDRESS = 7,
+	TAG_REGION = 8,
+	TAG_REGION_COUNT = 9,
+};
+
+/// Results are written to the file in `DEPICT_QBDI_OUTPUT`, so the program's output is untouched
+static FILE *records = NULL;
+
+static void write_tag(enum Tag tag) {
+	fputc(tag, records);
+}
+
+/// Little endian. -1 (no parent) is written as the maximum
+static void write_u64(uint64_t value) {
+	unsigned char bytes[8];
+	for (int i = 0; i < 8; i++) bytes[i] = (unsigned char)(value >> (8 * i));
+	fwrite(bytes, 1, sizeof(bytes), records);
+}
+
+static void write_str(const char *str) {
+	uint32_t length = (uint32_t)strlen(str);
+	unsigned char bytes[4];
+	for (int i = 0; i < 4; i++) bytes[i] = (unsigned char)(length >> (8 * i));
+	fwrite(bytes, 1, sizeof(bytes), records);
+	fwrite(str, 1, length, records);
+}
+
+/// Everything counted by a thread, after a `TAG_THREAD` record. Context ids are per thread
+static void write_thread(Thread *t) {
+	write_tag(TAG_THREAD);
+	write_u64(t->index);
+	while (t->frame_count > 0) pop_frame(t);
+	for (int i = 0; i < HASH_SIZE; i++) {
+		InclusiveEntry *e = t->inclusive_table[i];
+		while (e) {
+			write_tag(TAG_INCLUSIVE);
+			write_str(e->symbol);
+			write_str("total");
+			write_u64(e->counts[INCLUSIVE_TOTAL]);
+			for (size_t k = 0; k < INCLUSIVE_KINDS; k++) {
+				if (e->counts[k] != 0) {
+					write_tag(TAG_INCLUSIVE);
+					write_str(e->symbol);
+					write_str(inclusive_kinds[k]);
+					write_u64(e->counts[k]);
+				}
+			}
+			e = e->next;
+		}
+	}
+	for (int i = 0; i < t->context_count; i++) {
+		Context *context = &t->contexts[i];
+		write_tag(TAG_STACK);
+		write_u64(i);
+		write_u64((uint64_t)(int64_t)context->parent);
+		write_u64(context->counts[INCLUSIVE_TOTAL]);
+		write_str(context->entry->symbol);
+		for (size_t k = 0; k < INCLUSIVE_KINDS; k++) {
+			if (context->counts[k] != 0) {
+				write_tag(TAG_STACK_KIND);
+				write_u64(i);
+				write_str(inclusive_kinds[k]);
+				write_u64(context->counts[k]);
+			}
+		}
+	}
 	for (int i = 0; i < HASH_SIZE; i++) {
-		Entry *e = table[i];
+		CallEdge *e = t->call_table[i];
 		while (e) {
-			printf("depict_qbdi::%s/%s/%" PRIu64 "\n", e->symbol, e->kind, e->count);
+			write_tag(TAG_CALL);
+			write_str(e->caller->symbol);
+			write_str(e->callee->symbol);
+			write_u64(e->calls);
+			write_u64(e->inclusive);
 			e = e->next;
 		}
 	}
+	for (int i = 0; i < HASH_SIZE; i++) {
+		AddressEntry *e = t->address_table[i];
+		while (e) {
+			// Offset from where the image is loaded, so it can be looked up in its debug information
+			Dl_info info;
+			if (dladdr((void *)e->address, &info) != 0 && info.dli_fname != NULL) {
+				uint64_t offset = (uint64_t)e->address - (uint64_t)(uintptr_t)info.dli_fbase;
+				write_tag(TAG_ADDRESS);
+				write_u64(offset);
+				write_str(e->kind);
+				write_u64(e->count);
+				write_str(e->symbol);
+				write_str(info.dli_fname);
+			}
+			e = e->next;
+		}
+	}
+	for (int i = 0; i < HASH_SIZE; i++) {
+		Entry *e = t->table[i];
+		while (e) {
+			if (e->region == -1) {
+				write_tag(TAG_COUNT);
+			} else {
+				write_tag(TAG_REGION_COUNT);
+				write_u64(e->region);
+			}
+			write_str(e->symbol);
+			write_str(e->kind);
+			write_u64(e->count);
+			e = e->next;
+		}
+	}
+}
+
+int qbdipreload_on_exit(int status) { 
+	// Other processes, including forks which did not `exec`
+	if (output_path == NULL || getpid() != output_pid) {
+		return QBDIPRELOAD_NO_ERROR;
+	}
+	records = fopen(output_path, "wb");
+	if (records == NULL) {
+		fprintf(stderr, "depict: could not open DEPICT_QBDI_OUTPUT %s for results\n", output_path);
+		return QBDIPRELOAD_NO_ERROR;
+	}
+	pthread_mutex_lock(&regions_lock);
+	for (int i = 0; i < region_count; i++) {
+		write_tag(TAG_REGION);
+		write_u64(i);
+		write_u64((uint64_t)(int64_t)regions[i].parent);
+		write_str(regions[i].name);
+	}
+	pthread_mutex_unlock(&regions_lock);
+	// Threads still running are written as they are
+	pthread_mutex_lock(&threads_lock);
+	for (Thread *t = threads; t != NULL; t = t->next) {
+		write_thread(t);
+	}
+	pthread_mutex_unlock(&threads_lock);
+	fclose(records);
 
 	return QBDIPRELOAD_NO_ERROR;
 }
@@ -1,19 +1,101 @@
+use std::collections::HashMap;
 use std::io::BufRead;
 
 #[derive(Default, Debug)]
 pub struct Count {
-    pub total: u32,
-    pub mem_read: u32,
-    pub mem_write: u32,
-    pub stack_read: u32,
-    pub stack_write: u32,
-    pub call: u32,
+    pub total: u64,
+    pub mem_read: u64,
+    pub mem_write: u64,
+    pub stack_read: u64,
+    pub stack_write: u64,
+    pub call: u64,
+    /// `COND_BR` and `UNCOND_BR`
+    pub branch: u64,
+    pub r#return: u64,
+    /// `CMP` and `TEST` instructions, which are otherwise `BINARY` and `LOGICAL`
+    pub compare: u64,
+    pub logic: u64,
+    pub arithmetic: u64,
+    /// Other categories (lowercase)
+    pub others: HashMap<String, u64>,
+    /// Instructions from each ISA extension (lowercase), `*isa-ext-AVX2 1234`
+    pub isa_extensions: HashMap<String, u64>,
+}
+
+impl Count {
+    /// Compares are in their own field, so they are taken out of their category
+    fn finish(mut self, cmp: u64, test: u64) -> Self {
+        self.arithmetic = self.arithmetic.saturating_sub(cmp);
+        self.logic = self.logic.saturating_sub(test);
+        self.compare = cmp.checked_add(test).expect("compare count overflowed");
+        self
+    }
+
+    /// Reads a `*total 1234`, `*category-BINARY 1234`, `*isa-ext-AVX 1234` or instruction class
+    /// (`CMP 1234`) line
+    fn read_value(&mut self, line: &str, cmp: &mut u64, test: &mut u64) {
+        if let Some(rest) = line.strip_prefix("*total") {
+            self.total = rest.trim_start().parse().unwrap();
+        } else if let Some(rest) = line.strip_prefix("*stack-read ") {
+            self.stack_read = rest.trim_start().parse().unwrap();
+        } else if let Some(rest) = line.strip_prefix("*stack-write ") {
+            self.stack_write = rest.trim_start().parse().unwrap();
+        } else if let Some(rest) = line.strip_prefix("*mem-read ") {
+            self.mem_read = rest.trim_start().parse().unwrap();
+        } else if let Some(rest) = line.strip_prefix("*mem-write ") {
+            self.mem_write = rest.trim_start().parse().unwrap();
+        } else if let Some(rest) = line.strip_prefix("*isa-ext-") {
+            if let Some((extension, value)) = rest.split_once(char::is_whitespace)
+                && let Ok(value) = value.trim().parse::<u64>()
+            {
+                self.isa_extensions.insert(extension.to_lowercase(), value);
+            }
+        } else if let Some(rest) = line.strip_prefix("*category-") {
+            let Some((category, value)) = rest.split_once(char::is_whitespace) else {
+                return;
+            };
+            let Ok(value) = value.trim().parse::<u64>() else {
+                return;
+            };
+            match category {
+                "CALL" => self.call = value,
+                "COND_BR" | "UNCOND_BR" => {
+                    self.branch = self
+                        .branch
+                        .checked_add(value)
+                        .expect("branch count overflowed");
+                }
+                "RET" => self.r#return = value,
+                "LOGICAL" => self.logic = value,
+                "BINARY" => self.arithmetic = value,
+                category => {
+                    self.others.insert(category.to_lowercase(), value);
+                }
+            }
+        } else {
+            // Instruction class lines, `CMP 1234`
+            let mut parts = line.split_ascii_whitespace();
+            if let (Some(class @ ("CMP" | "TEST")), Some(value), None) =
+                (parts.next(), parts.next(), parts.next())
+                && let Ok(value) = value.parse::<u64>()
+            {
+                if class == "CMP" {
+                    *cmp = value;
+                } else {
+                    *test = value;
+                }
+            }
+        }
+    }
 }
 
 #[allow(clippy::collapsible_else_if)]
-pub fn parse(on: impl BufRead, skip_rust_internals: bool) -> Vec<(String, Count)> {
+/// Counts by function. Every function is included, filtering is up to the caller
+pub fn parse(on: impl BufRead) -> Vec<(String, Count)> {
     let mut section: String = String::default();
     let mut count = Count::default();
+    // Instruction class (opcode) counts, for compares
+    let (mut cmp, mut test) = (0, 0);
 
     let mut parts: Vec<(String, Count)> = Vec::new();
 
@@ -39,41 +121,222 @@ pub fn parse(on: impl BufRead, skip_rust_internals: bool) -> Vec<(String, Count)
         };
 
         if let Some(new_name) = name {
-            let skip = if skip_rust_internals {
-                section.contains("alloc") || section.contains("std") || section.contains("core")
-            } else {
-                false
-            };
-            let skip = skip || section.is_empty();
-
-            let count = std::mem::take(&mut count);
-            if !skip {
+            let count = std::mem::take(&mut count).finish(cmp, test);
+            (cmp, test) = (0, 0);
+            if !section.is_empty() {
                 parts.push((section, count));
             }
-            // TODO more efficient?
-            let name = new_name.replace("$LT$", "<").replace("$GT$", ">");
-            section = name;
+            // As SDE prints it. Rust names still have the escapes of the legacy mangling
+            section = new_name.to_owned();
         } else {
-            // values
-            if let Some(rest) = line.strip_prefix("*total") {
-                count.total = rest.trim_start().parse().unwrap();
-            } else if let Some(rest) = line.strip_prefix("*stack-read ") {
-                count.stack_read = rest.trim_start().parse().unwrap();
-            } else if let Some(rest) = line.strip_prefix("*stack-write ") {
-                count.stack_write = rest.trim_start().parse().unwrap();
-            } else if let Some(rest) = line.strip_prefix("*mem-read ") {
-                count.mem_read = rest.trim_start().parse().unwrap();
-            } else if let Some(rest) = line.strip_prefix("*mem-write ") {
-                count.mem_write = rest.trim_start().parse().unwrap();
-            } else if let Some(rest) = line.strip_prefix("*category-CALL") {
-                count.call = rest.trim_start().parse().unwrap();
-            }
+            count.read_value(&line, &mut cmp, &mut test);
         }
     }
 
     if !section.is_empty() {
-        parts.push((section, std::mem::take(&mut count)));
+        parts.push((section, count.finish(cmp, test)));
     }
 
     parts
 }
+
+/// A thread from an `# EMIT_DYNAMIC_STATS FOR TID 0 OS-TID 1234 EVENT: thread-end` section
+#[derive(Default, Debug)]
+pub struct Thread {
+    /// SDE thread id (in order of creation)
+    pub tid: usize,
+    /// From `# $dynamic-counts`
+    pub total: Count,
+    /// From `# $dynamic-counts-for-function: name`, as in [`parse`]. Empty if SDE was told to
+    /// omit them
+    pub functions: Vec<(String, Count)>,
+}
+
+/// The part of a thread section being read
+#[derive(Default)]
+enum Section {
+    #[default]
+    Other,
+    Total,
+    Function(String),
+}
+
+impl Thread {
+    fn finish_section(&mut self, section: Section, count: Count, cmp: u64, test: u64) {
+        match section {
+            Section::Other => {}
+            Section::Total => self.total = count.finish(cmp, test),
+            Section::Function(name) => self.functions.push((name, count.finish(cmp, test))),
+        }
+    }
+}
+
+/// Threads by SDE thread id
+pub fn parse_threads(on: impl BufRead) -> Vec<Thread> {
+    let mut threads: Vec<Thread> = Vec::new();
+    let mut current: Option<Thread> = None;
+    let mut section = Section::Other;
+    let mut count = Count::default();
+    let (mut cmp, mut test) = (0, 0);
+
+    for line in on.lines() {
+        let line = line.unwrap();
+        let starts_section = line.starts_with("# $");
+        if starts_section || line.starts_with("# EMIT") || line == "#GLOBAL_FUNCTION TOTALS " {
+            let count = std::mem::take(&mut count);
+            let section = std::mem::take(&mut section);
+            if let Some(ref mut thread) = current {
+                thread.finish_section(section, count, cmp, test);
+            }
+            (cmp, test) = (0, 0);
+        }
+
+        if let Some(rest) = line.strip_prefix("# EMIT_DYNAMIC_STATS FOR TID ") {
+            threads.extend(current.take());
+            current = rest
+                .split_ascii_whitespace()
+                .next()
+                .and_then(|tid| tid.parse().ok())
+                .map(|tid| Thread {
+                    tid,
+                    ..Thread::default()
+                });
+        } else if line.starts_with("# EMIT") || line == "#GLOBAL_FUNCTION TOTALS " {
+            threads.extend(current.take());
+        } else if starts_section {
+            section = if line.trim_end() == "# $dynamic-counts" {
+                Section::Total
+            } else if let Some(rest) = line.strip_prefix("# $dynamic-counts-for-function: ") {
+                let name = rest.split_once(' ').map_or(rest, |(name, _)| name);
+                Section::Function(name.to_owned())
+            } else {
+                Section::Other
+            };
+        } else if current.is_some() && !matches!(section, Section::Other) {
+            count.read_value(&line, &mut cmp, &mut test);
+        }
+    }
+    if let Some(mut thread) = current {
+        thread.finish_section(section, count, cmp, test);
+        threads.push(thread);
+    }
+
+    threads.sort_by_key(|thread| thread.tid);
+    threads
+}
+
+/// A basic block from `-top_blocks`. Every instruction in it is executed `executions` times
+#[derive(Default, Debug)]
+pub struct Block {
+    /// Runtime address of the first instruction
+    pub address: u64,
+    pub instructions: u64,
+    pub executions: u64,
+    pub function: String,
+    pub image: String,
+    /// Of the first instruction, from where the image is loaded
+    pub offset: u64,
+    /// Runtime address and text of each instruction. May be empty
+    pub disassembly: Vec<(u64, String)>,
+}
+
+/// Parses `BLOCK: 1 PC: 7f.. ICOUNT: 100 EXECUTIONS: 10 ... FN: name IMG: path OFFSET: 1130`
+/// headers and the `XDIS 7f..: ...` disassembly following them
+pub fn parse_blocks(on: impl BufRead) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+
+    for line in on.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let line = line.trim_start_matches(['#', ' ']);
+
+        if line.starts_with("BLOCK:") {
+            let mut block = Block::default();
+            let mut parts = line.split_ascii_whitespace();
+            while let Some(key) = parts.next() {
+                let Some(value) = parts.next() else {
+                    break;
+                };
+                match key {
+                    "PC:" => block.address = u64::from_str_radix(value, 16).unwrap_or_default(),
+                    "ICOUNT:" => block.instructions = value.parse().unwrap_or_default(),
+                    "EXECUTIONS:" => block.executions = value.parse().unwrap_or_default(),
+                    // Demangled by the caller
+                    "FN:" => block.function = value.to_owned(),
+                    "IMG:" => block.image = value.to_owned(),
+                    "OFFSET:" => block.offset = u64::from_str_radix(value, 16).unwrap_or_default(),
+                    _ => {}
+                }
+            }
+            blocks.push(block);
+        } else if let Some(rest) = line.strip_prefix("XDIS ")
+            && let Some(block) = blocks.last_mut()
+            && let Some((address, instruction)) = rest.split_once(':')
+            && let Ok(address) = u64::from_str_radix(address.trim(), 16)
+        {
+            block
+                .disassembly
+                .push((address, instruction.trim().to_owned()));
+        }
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn counts_above_u32() {
+        let mix = "#GLOBAL_FUNCTION TOTALS \n\
+            # $dynamic-counts-for-function: run \n\
+            *total 60000000000\n\
+            *category-COND_BR 4294967296\n\
+            *category-UNCOND_BR 4294967296\n\
+            *isa-ext-AVX2 5000000000\n";
+        let parts = super::parse(mix.as_bytes());
+        let [(name, count)] = parts.as_slice() else {
+            panic!("expected one function, found {parts:?}");
+        };
+        assert_eq!(name, "run");
+        assert_eq!(count.total, 60_000_000_000);
+        assert_eq!(count.branch, 1 << 33);
+        assert_eq!(count.isa_extensions["avx2"], 5_000_000_000);
+    }
+
+    #[test]
+    fn threads_with_functions() {
+        let mix = "# EMIT_DYNAMIC_STATS FOR TID 1  OS-TID 101 EVENT: thread-end\n\
+            # $dynamic-counts\n\
+            *category-BINARY 600\n\
+            CMP 100\n\
+            *total 3000\n\
+            # $dynamic-counts-for-function: work  IMG: /tmp/x\n\
+            *total 2900\n\
+            *category-BINARY 500\n\
+            CMP 100\n\
+            # $dynamic-counts-for-function: start_thread \n\
+            *total 100\n\
+            # EMIT_DYNAMIC_STATS FOR TID 0  OS-TID 100 EVENT: thread-end\n\
+            # $dynamic-counts\n\
+            *total 7000\n\
+            # EMIT_GLOBAL_DYNAMIC_STATS EVENT: fini\n\
+            #GLOBAL_FUNCTION TOTALS \n\
+            # $dynamic-counts-for-function: work \n\
+            *total 10000\n";
+        let threads = super::parse_threads(mix.as_bytes());
+        let [main, worker] = threads.as_slice() else {
+            panic!("expected two threads, found {threads:?}");
+        };
+        assert_eq!((main.tid, main.total.total), (0, 7000));
+        assert!(main.functions.is_empty());
+        assert_eq!((worker.tid, worker.total.total), (1, 3000));
+        assert_eq!((worker.total.arithmetic, worker.total.compare), (500, 100));
+        let functions: Vec<(&str, u64, u64)> = worker
+            .functions
+            .iter()
+            .map(|(name, count)| (name.as_str(), count.total, count.compare))
+            .collect();
+        assert_eq!(functions, [("work", 2900, 100), ("start_thread", 100, 0)]);
+    }
+}
@@ -0,0 +1,592 @@
+//! Reading back results files (written with `--write-results-to`) and diffing them per symbol
+
+use crate::{Entry, Statistics};
+
+use std::collections::HashMap;
+
+/// Symbol instruction counts read from a results file
+#[derive(Debug, Default)]
+pub struct Results {
+    pub total: Statistics,
+    pub symbols: Vec<Entry>,
+    /// Metrics present in the file, in the order they are shown. Just `total` unless it was
+    /// written with `--breakdown`
+    pub metrics: Vec<String>,
+}
+
+impl Results {
+    /// From the output of a counting tool (rather than a file)
+    #[must_use]
+    pub fn from_output(symbols: &[Entry], total: &Statistics) -> Self {
+        Self {
+            total: total.clone(),
+            symbols: symbols.to_vec(),
+            metrics: crate::metric_names(
+                std::iter::once(total).chain(symbols.iter().map(|entry| &entry.statistics)),
+            ),
+        }
+    }
+
+    /// Region rows are prefixed with `[region] ` (including their total)
+    fn push(&mut self, entry: Entry) {
+        if entry.symbol_name == "Total" {
+            self.total = entry.statistics;
+        } else {
+            self.symbols.push(entry);
+        }
+    }
+
+    fn add_metric(&mut self, name: &str) {
+        if !self.metrics.iter().any(|metric| metric == name) {
+            self.metrics.push(name.to_owned());
+        }
+    }
+}
+
+/// # Errors
+/// If the JSON is invalid or not in the form written by `print_results`
+pub fn parse_json_results(content: &str) -> Result<Results, String> {
+    use simple_json_parser::{JSONKey, RootJSONValue};
+
+    let mut results = Results::default();
+    // Region or thread (if any) and index of the entry being read
+    let mut current: Option<((Option<String>, usize), Entry)> = None;
+    let mut error = None;
+
+    simple_json_parser::parse(content, |keys, value| {
+        // Either an array of entries or (with regions or threads)
+        // `{ "symbols": [...], "regions": { name: [...] }, "threads": { index: [...] } }`
+        let (section, keys) = match keys {
+            [JSONKey::Slice("symbols"), rest @ ..] => (None, rest),
+            [JSONKey::Slice("regions"), JSONKey::Slice(region), rest @ ..] => {
+                (Some((*region).to_owned()), rest)
+            }
+            [JSONKey::Slice("threads"), JSONKey::Slice(thread), rest @ ..] => {
+                (Some(format!("thread {thread}")), rest)
+            }
+            keys => (None, keys),
+        };
+        let (Some(JSONKey::Index(idx)), Some(JSONKey::Slice(key))) = (keys.first(), keys.get(1))
+        else {
+            error = Some("expected array of objects".to_owned());
+            return;
+        };
+        if current
+            .as_ref()
+            .is_none_or(|((current_section, current), _)| {
+                current != idx || *current_section != section
+            })
+        {
+            if let Some((_, entry)) = current.take() {
+                results.push(entry);
+            }
+            current = Some((
+                (section.clone(), *idx),
+                Entry {
+                    symbol_name: String::new(),
+                    statistics: Statistics::default(),
+                    inclusive: None,
+                },
+            ));
+        }
+        let entry = &mut current.as_mut().unwrap().1;
+        match (*key, keys.get(2), value) {
+            ("symbol_name", None, RootJSONValue::String(name)) => {
+                let name = name.replace("\\\"", "\"").replace("\\\\", "\\");
+                // Matches the CSV output
+                entry.symbol_name = match section {
+                    Some(ref section) => format!("[{section}] {name}"),
+                    None => name,
+                };
+            }
+            ("inclusive", None, RootJSONValue::Number(value)) => {
+                if let Ok(value) = value.parse() {
+                    entry.inclusive = Some(Statistics {
+                        total: value,
+                        ..Statistics::default()
+                    });
+                }
+            }
+            // Older files have kinds outside of the known metrics in an object
+            ("other", Some(JSONKey::Slice(kind)), RootJSONValue::Number(value)) => {
+                if let Ok(value) = value.parse() {
+                    entry.statistics.set(kind, value);
+                    results.add_metric(kind);
+                }
+            }
+            // From `--merge-generics`, not a metric
+            ("instantiations", None, _) => {}
+            (key, None, RootJSONValue::Number(value)) => match value.parse() {
+                Ok(value) => {
+                    entry.statistics.set(key, value);
+                    results.add_metric(key);
+                }
+                Err(_) => error = Some(format!("invalid count {value:?} for {key:?}")),
+            },
+            _ => {}
+        }
+    })
+    .map_err(|error| error.to_string())?;
+
+    if let Some(error) = error {
+        return Err(error);
+    }
+    if let Some((_, entry)) = current {
+        results.push(entry);
+    }
+    Ok(results)
+}
+
+/// Splits a CSV line, respecting (double) quoted fields
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            chr => field.push(chr),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// # Errors
+/// If the CSV is not in the form written by `print_results`
+pub fn parse_csv_results(content: &str) -> Result<Results, String> {
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    let header = split_csv_line(lines.next().ok_or("empty file")?);
+    if header.first().map(String::as_str) != Some("symbol name") {
+        return Err("expected 'symbol name' as first column".to_owned());
+    }
+    let mut results = Results::default();
+    for name in &header[1..] {
+        if name != "inclusive" {
+            results.add_metric(name);
+        }
+    }
+    for line in lines {
+        let fields = split_csv_line(line);
+        let mut fields = fields.into_iter();
+        let mut entry = Entry {
+            symbol_name: fields.next().unwrap_or_default(),
+            statistics: Statistics::default(),
+            inclusive: None,
+        };
+        for (name, value) in header[1..].iter().zip(fields) {
+            // Empty for rows without inclusive counts
+            if name == "inclusive" && value.trim().is_empty() {
+                continue;
+            }
+            let value = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid count {value:?} for {name:?}"))?;
+            if name == "inclusive" {
+                entry.inclusive = Some(Statistics {
+                    total: value,
+                    ..Statistics::default()
+                });
+            } else {
+                entry.statistics.set(name, value);
+            }
+        }
+        results.push(entry);
+    }
+    Ok(results)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug)]
+pub struct MetricDiff {
+    pub name: String,
+    pub base: u64,
+    pub new: u64,
+}
+
+impl MetricDiff {
+    /// Saturates at the bounds of `i64`
+    #[must_use]
+    pub fn absolute(&self) -> i64 {
+        let difference = i128::from(self.new) - i128::from(self.base);
+        i64::try_from(difference).unwrap_or(if difference < 0 { i64::MIN } else { i64::MAX })
+    }
+
+    /// `None` if the base is zero
+    #[must_use]
+    pub fn percentage(&self) -> Option<f64> {
+        (self.base != 0).then(|| self.absolute() as f64 / self.base as f64 * 100.)
+    }
+}
+
+#[derive(Debug)]
+pub struct SymbolDiff {
+    pub symbol_name: String,
+    pub change: Change,
+    /// First is always `total`
+    pub metrics: Vec<MetricDiff>,
+}
+
+impl SymbolDiff {
+    fn new(
+        symbol_name: String,
+        change: Change,
+        metrics: &[String],
+        base: &Statistics,
+        new: &Statistics,
+    ) -> Self {
+        Self {
+            symbol_name,
+            change,
+            metrics: metrics
+                .iter()
+                .map(|name| MetricDiff {
+                    name: name.clone(),
+                    base: base.get(name),
+                    new: new.get(name),
+                })
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn total(&self) -> &MetricDiff {
+        &self.metrics[0]
+    }
+}
+
+#[derive(Debug)]
+pub struct Comparison {
+    pub total: SymbolDiff,
+    /// Sorted by the biggest regression (in `total`) first
+    pub symbols: Vec<SymbolDiff>,
+}
+
+/// Compares the metrics present in both files
+#[must_use]
+pub fn compare(base: &Results, new: &Results) -> Comparison {
+    let mut metrics = vec!["total".to_owned()];
+    metrics.extend(
+        base.metrics
+            .iter()
+            .filter(|name| *name != "total" && new.metrics.contains(name))
+            .cloned(),
+    );
+
+    let empty = Statistics::default();
+    let base_symbols: HashMap<&str, &Statistics> = base
+        .symbols
+        .iter()
+        .map(|entry| (entry.symbol_name.as_str(), &entry.statistics))
+        .collect();
+    let new_symbols: HashMap<&str, &Statistics> = new
+        .symbols
+        .iter()
+        .map(|entry| (entry.symbol_name.as_str(), &entry.statistics))
+        .collect();
+
+    let mut symbols: Vec<SymbolDiff> = base
+        .symbols
+        .iter()
+        .map(|entry| {
+            let (change, new) = match new_symbols.get(entry.symbol_name.as_str()) {
+                Some(new) => (Change::Changed, *new),
+                None => (Change::Removed, &empty),
+            };
+            SymbolDiff::new(
+                entry.symbol_name.clone(),
+                change,
+                &metrics,
+                &entry.statistics,
+                new,
+            )
+        })
+        .collect();
+
+    for entry in &new.symbols {
+        if !base_symbols.contains_key(entry.symbol_name.as_str()) {
+            symbols.push(SymbolDiff::new(
+                entry.symbol_name.clone(),
+                Change::Added,
+                &metrics,
+                &empty,
+                &entry.statistics,
+            ));
+        }
+    }
+
+    symbols.sort_by_key(|diff| std::cmp::Reverse(diff.total().absolute()));
+
+    Comparison {
+        total: SymbolDiff::new(
+            "Total".to_owned(),
+            Change::Changed,
+            &metrics,
+            &base.total,
+            &new.total,
+        ),
+        symbols,
+    }
+}
+
+/// Allowed increase before something counts as a regression
+#[derive(Clone, Copy, Debug)]
+pub enum Threshold {
+    /// `2%`
+    Percentage(f64),
+    /// `1000`
+    Absolute(u64),
+}
+
+impl std::str::FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(on: &str) -> Result<Self, Self::Err> {
+        let on = on.trim();
+        if let Some(percentage) = on.strip_suffix('%') {
+            percentage
+                .trim()
+                .parse()
+                .map(Self::Percentage)
+                .map_err(|_| format!("invalid percentage {on:?}"))
+        } else {
+            on.parse()
+                .map(Self::Absolute)
+                .map_err(|_| format!("invalid threshold {on:?}. expected count or percentage"))
+        }
+    }
+}
+
+impl std::fmt::Display for Threshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Percentage(percentage) => write!(f, "{percentage}%"),
+            Self::Absolute(count) => write!(f, "{count}"),
+        }
+    }
+}
+
+impl Threshold {
+    #[must_use]
+    pub fn exceeded_by(&self, metric: &MetricDiff) -> bool {
+        match self {
+            Self::Percentage(percentage) => match metric.percentage() {
+                Some(change) => change > *percentage,
+                // From nothing to something
+                None => metric.new > 0,
+            },
+            Self::Absolute(count) => metric.absolute() > i64::try_from(*count).unwrap_or(i64::MAX),
+        }
+    }
+}
+
+/// Per-symbol thresholds from a JSON object of symbol name to threshold. `Total` refers to the
+/// total. For example `{ "Total": "1%", "my_crate::parse": 5000 }`
+///
+/// # Errors
+/// If the JSON is invalid or a threshold cannot be parsed
+pub fn parse_thresholds(content: &str) -> Result<Vec<(String, Threshold)>, String> {
+    use simple_json_parser::{JSONKey, RootJSONValue};
+
+    let mut thresholds = Vec::new();
+    let mut error = None;
+    simple_json_parser::parse(content, |keys, value| {
+        let [JSONKey::Slice(symbol_name)] = keys else {
+            error = Some("expected object of symbol name to threshold".to_owned());
+            return;
+        };
+        let (RootJSONValue::String(value) | RootJSONValue::Number(value)) = value else {
+            error = Some(format!("expected threshold for {symbol_name:?}"));
+            return;
+        };
+        match value.parse() {
+            Ok(threshold) => thresholds.push(((*symbol_name).to_owned(), threshold)),
+            Err(reason) => error = Some(reason),
+        }
+    })
+    .map_err(|error| error.to_string())?;
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(thresholds),
+    }
+}
+
+#[derive(Debug)]
+pub struct Regression<'a> {
+    pub symbol_name: &'a str,
+    pub total: &'a MetricDiff,
+    pub threshold: Threshold,
+}
+
+/// `default` applies to the total. `overrides` replace it for the total (as `Total`) and add
+/// checks for other symbols
+#[must_use]
+pub fn find_regressions<'a>(
+    comparison: &'a Comparison,
+    default: Option<Threshold>,
+    overrides: &[(String, Threshold)],
+) -> Vec<Regression<'a>> {
+    let threshold_for = |symbol_name: &str| {
+        overrides
+            .iter()
+            .find_map(|(name, threshold)| (name == symbol_name).then_some(*threshold))
+    };
+
+    let mut regressions = Vec::new();
+    let total_threshold = threshold_for("Total").or(default);
+    if let Some(threshold) = total_threshold
+        && threshold.exceeded_by(comparison.total.total())
+    {
+        regressions.push(Regression {
+            symbol_name: &comparison.total.symbol_name,
+            total: comparison.total.total(),
+            threshold,
+        });
+    }
+
+    for (symbol_name, threshold) in overrides {
+        if symbol_name == "Total" {
+            continue;
+        }
+        let Some(diff) = comparison
+            .symbols
+            .iter()
+            .find(|diff| &diff.symbol_name == symbol_name)
+        else {
+            continue;
+        };
+        if threshold.exceeded_by(diff.total()) {
+            regressions.push(Regression {
+                symbol_name: &diff.symbol_name,
+                total: diff.total(),
+                threshold: *threshold,
+            });
+        }
+    }
+
+    regressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Results, Threshold, compare, find_regressions, parse_thresholds};
+    use crate::{Entry, Statistics};
+
+    fn results(total: u64, symbols: &[(&str, u64)]) -> Results {
+        let statistics = |total| Statistics {
+            total,
+            ..Statistics::default()
+        };
+        Results {
+            total: statistics(total),
+            symbols: symbols
+                .iter()
+                .map(|(name, total)| Entry {
+                    symbol_name: (*name).to_owned(),
+                    statistics: statistics(*total),
+                    inclusive: None,
+                })
+                .collect(),
+            metrics: vec!["total".to_owned()],
+        }
+    }
+
+    fn regressed<'a>(
+        comparison: &'a super::Comparison,
+        default: Option<Threshold>,
+        overrides: &[(String, Threshold)],
+    ) -> Vec<&'a str> {
+        find_regressions(comparison, default, overrides)
+            .into_iter()
+            .map(|regression| regression.symbol_name)
+            .collect()
+    }
+
+    #[test]
+    fn default_thresholds() {
+        let base = results(1000, &[("parse", 600), ("emit", 400)]);
+        let new = results(1030, &[("parse", 630), ("emit", 400)]);
+        let comparison = compare(&base, &new);
+
+        assert_eq!(
+            regressed(&comparison, Some(Threshold::Percentage(2.)), &[]),
+            ["Total"]
+        );
+        assert!(regressed(&comparison, Some(Threshold::Percentage(3.)), &[]).is_empty());
+        assert_eq!(
+            regressed(&comparison, Some(Threshold::Absolute(29)), &[]),
+            ["Total"]
+        );
+        assert!(regressed(&comparison, Some(Threshold::Absolute(30)), &[]).is_empty());
+        assert!(regressed(&comparison, None, &[]).is_empty());
+
+        // Improvements are never regressions
+        let comparison = compare(&new, &base);
+        assert!(regressed(&comparison, Some(Threshold::Absolute(0)), &[]).is_empty());
+    }
+
+    #[test]
+    fn per_symbol_overrides() {
+        let base = results(1000, &[("parse", 600), ("emit", 400)]);
+        let new = results(1030, &[("parse", 630), ("emit", 400), ("added", 0)]);
+        let comparison = compare(&base, &new);
+
+        let overrides = parse_thresholds(
+            r#"{ "Total": "5%", "parse": "4%", "emit": 0, "missing": 0, "added": "1%" }"#,
+        )
+        .unwrap();
+        // `Total` replaces the default
+        assert_eq!(
+            regressed(&comparison, Some(Threshold::Absolute(0)), &overrides),
+            ["parse"]
+        );
+
+        let overrides = parse_thresholds(r#"{ "emit": 1 }"#).unwrap();
+        assert!(regressed(&comparison, None, &overrides).is_empty());
+    }
+
+    #[test]
+    fn missing_and_zero_baseline() {
+        let base = results(100, &[("zero", 0)]);
+        let new = results(110, &[("zero", 5), ("added", 10)]);
+        let comparison = compare(&base, &new);
+
+        // A symbol missing from the baseline has a base of zero, so any increase is over a
+        // percentage
+        let overrides = parse_thresholds(r#"{ "zero": "1000%", "added": "1000%" }"#).unwrap();
+        assert_eq!(regressed(&comparison, None, &overrides), ["zero", "added"]);
+        let overrides = parse_thresholds(r#"{ "zero": 5, "added": 9 }"#).unwrap();
+        assert_eq!(regressed(&comparison, None, &overrides), ["added"]);
+
+        // Still zero
+        let new = results(100, &[("zero", 0)]);
+        let comparison = compare(&base, &new);
+        let overrides = parse_thresholds(r#"{ "zero": "0%" }"#).unwrap();
+        assert!(regressed(&comparison, None, &overrides).is_empty());
+    }
+
+    #[test]
+    fn invalid_thresholds() {
+        assert!(parse_thresholds(r#"{ "parse": "two%" }"#).is_err());
+        assert!(parse_thresholds(r#"{ "parse": -1 }"#).is_err());
+        assert!(parse_thresholds(r#"[1]"#).is_err());
+        assert!(matches!(
+            " 2.5 % ".parse(),
+            Ok(Threshold::Percentage(percentage)) if percentage == 2.5
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+//! `estimated_cycles`. Weights instructions by their kind, so loads and divisions cost more than
+//! additions. The default weights are rough latencies for a modern out-of-order core
+
+use crate::{Entry, Statistics, ToolOutput};
+
+/// Cycles for every instruction, plus extra cycles for each kind. An instruction can be counted in
+/// several kinds (a load which is also an addition)
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    /// For every instruction
+    pub base: f64,
+    pub mem_read: f64,
+    pub mem_write: f64,
+    pub stack_read: f64,
+    pub stack_write: f64,
+    pub call: f64,
+    pub branch: f64,
+    pub r#return: f64,
+    pub compare: f64,
+    pub logic: f64,
+    pub arithmetic: f64,
+    /// For other metrics (SDE categories, QBDI mnemonics) containing the key (ignoring case). The
+    /// longest key is used. Metrics without a key have no extra cost
+    pub others: Vec<(String, f64)>,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            base: 1.,
+            mem_read: 3.,
+            mem_write: 1.,
+            stack_read: 2.,
+            stack_write: 1.,
+            call: 2.,
+            branch: 1.,
+            r#return: 2.,
+            compare: 0.,
+            logic: 0.,
+            arithmetic: 0.,
+            others: [("div", 20.), ("sqrt", 15.), ("mul", 2.)]
+                .into_iter()
+                .map(|(kind, weight)| (kind.to_owned(), weight))
+                .collect(),
+        }
+    }
+}
+
+impl CostModel {
+    /// Default weights replaced by those in a JSON object. For example
+    /// `{ "mem_read": 4, "others": { "div": 25, "avx": 1.5 } }`
+    ///
+    /// # Errors
+    /// If the JSON is invalid or has unknown keys
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        use simple_json_parser::{JSONKey, RootJSONValue};
+
+        let mut model = Self::default();
+        let mut error = None;
+        simple_json_parser::parse(content, |keys, value| {
+            let RootJSONValue::Number(value) = value else {
+                error = Some(format!("expected number for {keys:?}"));
+                return;
+            };
+            let Ok(value) = value.parse::<f64>() else {
+                error = Some(format!("invalid weight {value:?}"));
+                return;
+            };
+            let weight = match keys {
+                [JSONKey::Slice("others"), JSONKey::Slice(kind)] => {
+                    let kind = kind.to_lowercase();
+                    match model
+                        .others
+                        .iter_mut()
+                        .find(|(existing, _)| *existing == kind)
+                    {
+                        Some((_, weight)) => weight,
+                        None => {
+                            model.others.push((kind, value));
+                            return;
+                        }
+                    }
+                }
+                [JSONKey::Slice("base")] => &mut model.base,
+                [JSONKey::Slice("mem_read")] => &mut model.mem_read,
+                [JSONKey::Slice("mem_write")] => &mut model.mem_write,
+                [JSONKey::Slice("stack_read")] => &mut model.stack_read,
+                [JSONKey::Slice("stack_write")] => &mut model.stack_write,
+                [JSONKey::Slice("call")] => &mut model.call,
+                [JSONKey::Slice("branch")] => &mut model.branch,
+                [JSONKey::Slice("return")] => &mut model.r#return,
+                [JSONKey::Slice("compare")] => &mut model.compare,
+                [JSONKey::Slice("logic")] => &mut model.logic,
+                [JSONKey::Slice("arithmetic")] => &mut model.arithmetic,
+                keys => {
+                    error = Some(format!("unknown cost {keys:?}"));
+                    return;
+                }
+            };
+            *weight = value;
+        })
+        .map_err(|error| error.to_string())?;
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(model),
+        }
+    }
+
+    fn other(&self, kind: &str) -> f64 {
+        let kind = kind.to_lowercase();
+        self.others
+            .iter()
+            .filter(|(key, _)| kind.contains(key.as_str()))
+            .max_by_key(|(key, _)| key.len())
+            .map_or(0., |(_, weight)| *weight)
+    }
+
+    #[must_use]
+    pub fn estimate(&self, statistics: &Statistics) -> u64 {
+        let mut cycles = 0.;
+        for (name, count) in statistics.metrics() {
+            let weight = match name {
+                "total" => self.base,
+                "estimated_cycles" => 0.,
+                "mem_read" => self.mem_read,
+                "mem_write" => self.mem_write,
+                "stack_read" => self.stack_read,
+                "stack_write" => self.stack_write,
+                "call" => self.call,
+                "branch" => self.branch,
+                "return" => self.r#return,
+                "compare" => self.compare,
+                "logic" => self.logic,
+                "arithmetic" => self.arithmetic,
+                other => self.other(other),
+            };
+            cycles += weight * count as f64;
+        }
+        // Saturates
+        cycles.round() as u64
+    }
+
+    /// Sets `estimated_cycles` everywhere in the results
+    pub fn apply(&self, output: &mut ToolOutput) {
+        let ToolOutput::SymbolInstructionCounts {
+            symbols,
+            total,
+            regions,
+            calls,
+            stacks,
+            lines,
+            threads,
+            ..
+        } = output
+        else {
+            return;
+        };
+
+        let set = |statistics: &mut Statistics| {
+            let cycles = self.estimate(statistics);
+            statistics.set("estimated_cycles", cycles);
+        };
+        let set_entries = |entries: &mut [Entry]| {
+            for entry in entries {
+                set(&mut entry.statistics);
+                if let Some(ref mut inclusive) = entry.inclusive {
+                    set(inclusive);
+                }
+            }
+        };
+
+        set_entries(symbols);
+        for region in regions {
+            set_entries(&mut region.symbols);
+            set(&mut region.total);
+        }
+        for thread in threads {
+            set_entries(&mut thread.symbols);
+            set(&mut thread.total);
+        }
+        set(total);
+        for call in calls {
+            set(&mut call.inclusive);
+        }
+        for stack in stacks {
+            set(&mut stack.statistics);
+        }
+        for line in lines {
+            set(&mut line.statistics);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CostModel;
+    use crate::Statistics;
+
+    fn statistics(metrics: &[(&str, u64)]) -> Statistics {
+        let mut statistics = Statistics::default();
+        for (name, count) in metrics {
+            statistics.set(name, *count);
+        }
+        statistics
+    }
+
+    fn statistics_with_other(kind: &str, count: u64) -> Statistics {
+        statistics(&[("total", count), (kind, count)])
+    }
+
+    #[test]
+    fn default_weights() {
+        let model = CostModel::default();
+        // 10 + 3 * 2 (reads) + 1 * 1 (write)
+        let statistics = statistics(&[("total", 10), ("mem_read", 2), ("mem_write", 1)]);
+        assert_eq!(model.estimate(&statistics), 17);
+        // The longest key (`sqrt` over `div`) is used and case is ignored
+        assert_eq!(
+            model.estimate(&statistics_with_other("FDIV_SQRT", 2)),
+            2 + 2 * 15
+        );
+        // Metrics without a key have no extra cost
+        assert_eq!(model.estimate(&statistics_with_other("nop", 5)), 5);
+    }
+
+    #[test]
+    fn overrides_from_json() {
+        let model = CostModel::from_json(
+            r#"{ "mem_read": 4, "base": 0.5, "others": { "DIV": 25, "avx": 1.5 } }"#,
+        )
+        .unwrap();
+        assert_eq!(model.mem_read, 4.);
+        assert_eq!(model.base, 0.5);
+        // Unchanged
+        assert_eq!(model.mem_write, 1.);
+        assert_eq!(model.estimate(&statistics_with_other("div", 2)), 1 + 2 * 25);
+        assert_eq!(model.estimate(&statistics_with_other("avx_add", 2)), 1 + 3);
+        assert_eq!(model.estimate(&statistics_with_other("mul", 2)), 1 + 2 * 2);
+    }
+
+    #[test]
+    fn unknown_cost() {
+        let error = CostModel::from_json(r#"{ "mem_reads": 4 }"#).unwrap_err();
+        assert!(error.starts_with("unknown cost"), "{error}");
+        let error = CostModel::from_json(r#"{ "others": { "div": "a lot" } }"#).unwrap_err();
+        assert!(error.starts_with("expected number"), "{error}");
+    }
+}
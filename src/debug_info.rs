@@ -0,0 +1,175 @@
+//! Source lines from DWARF debug information, for attributing instructions to lines. Only ELF
+//! files with uncompressed debug sections are supported
+
+use crate::elf::ElfFile;
+use crate::{Entry, Line, Statistics};
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type Reader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// Instructions executed at addresses, by image path and offset from where the image is loaded.
+/// With the (demangled) symbol name the address is in
+pub type Addresses = HashMap<(String, u64), (String, Statistics)>;
+
+pub struct DebugInfo {
+    context: addr2line::Context<Reader>,
+    /// Virtual address the image starts at (the lowest loadable segment, page aligned). Offsets
+    /// from where it is loaded are relative to this
+    base: u64,
+}
+
+impl DebugInfo {
+    /// `None` if the file cannot be read, is not ELF or does not have line information
+    #[must_use]
+    pub fn load(path: &str) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let file = ElfFile::parse(&data)?;
+        file.section(".debug_line")?;
+
+        let dwarf = gimli::Dwarf::load(|id| -> Result<Reader, ()> {
+            let data = file
+                .section(id.name())
+                .map_or(&[][..], |section| section.data);
+            Ok(gimli::EndianRcSlice::new(
+                Rc::from(data),
+                gimli::RunTimeEndian::Little,
+            ))
+        })
+        .ok()?;
+        let context = addr2line::Context::from_dwarf(dwarf).ok()?;
+        let base = file
+            .segments
+            .iter()
+            .map(|segment| segment.virtual_address)
+            .min()
+            .unwrap_or_default()
+            & !0xfff;
+        Some(Self { context, base })
+    }
+
+    /// File and line for an offset from where the image is loaded
+    #[must_use]
+    pub fn location(&self, offset: u64) -> Option<(&str, u32)> {
+        let location = self.context.find_location(self.base + offset).ok()??;
+        Some((location.file?, location.line?))
+    }
+
+    /// (Demangled) names of the functions inlined at an offset from where the image is loaded,
+    /// outermost first. Does not include the function they are inlined into
+    #[must_use]
+    pub fn inlined_functions(&self, offset: u64) -> Vec<String> {
+        let Ok(mut frames) = self
+            .context
+            .find_frames(self.base + offset)
+            .skip_all_loads()
+        else {
+            return Vec::new();
+        };
+        // Innermost first
+        let mut names = Vec::new();
+        while let Ok(Some(frame)) = frames.next() {
+            let name = frame
+                .function
+                .as_ref()
+                .and_then(|function| function.raw_name().ok())
+                .map_or_else(
+                    || "[unknown inlined]".to_owned(),
+                    |name| crate::tools::demangle(&name),
+                );
+            names.push(name);
+        }
+        names.pop();
+        names.reverse();
+        names
+    }
+}
+
+/// For tools which only have instructions by address after running. Moves instructions executed
+/// in inlined functions from the function they are inlined into to a virtual entry for the
+/// innermost inlined function. The symbol of the addresses is updated to match
+pub fn attribute_inlined(
+    symbols: &mut Vec<Entry>,
+    addresses: &mut Addresses,
+    filter: &crate::filter::Filter,
+) {
+    let name = |name: &str| -> String { filter.name(name).to_owned() };
+
+    let mut images: HashMap<String, Option<DebugInfo>> = HashMap::new();
+    for ((path, offset), (symbol_name, statistics)) in addresses.iter_mut() {
+        let debug_info = images
+            .entry(path.clone())
+            .or_insert_with_key(|path| DebugInfo::load(path));
+        let Some(inlined) = debug_info
+            .as_ref()
+            .and_then(|debug_info| debug_info.inlined_functions(*offset).pop())
+        else {
+            continue;
+        };
+
+        let (outer, inner) = (name(symbol_name), name(&inlined));
+        if outer == inner {
+            *symbol_name = inlined;
+            continue;
+        }
+        let Some(entry) = symbols.iter_mut().find(|entry| entry.symbol_name == outer) else {
+            continue;
+        };
+        entry.statistics -= &*statistics;
+        let tracks_calls = entry.inclusive.is_some();
+        match symbols.iter_mut().find(|entry| entry.symbol_name == inner) {
+            Some(entry) => {
+                entry.statistics += statistics.clone();
+                // Inlined functions are never called, so inclusive is the same as exclusive
+                if let Some(ref mut inclusive) = entry.inclusive
+                    && !filter.is_bucket(&inner)
+                {
+                    *inclusive += statistics.clone();
+                }
+            }
+            None => symbols.push(Entry {
+                inclusive: (tracks_calls && !filter.is_bucket(&inner)).then(|| statistics.clone()),
+                symbol_name: inner,
+                statistics: statistics.clone(),
+            }),
+        }
+        *symbol_name = inlined;
+    }
+}
+
+/// Sums the instructions executed at each address by line. Addresses without line information are
+/// skipped
+#[must_use]
+pub fn lines(addresses: Addresses) -> Vec<Line> {
+    let mut images: HashMap<String, Option<DebugInfo>> = HashMap::new();
+    let mut lines: HashMap<(String, String, u32), Statistics> = HashMap::new();
+    for ((path, offset), (symbol_name, statistics)) in addresses {
+        let debug_info = images
+            .entry(path)
+            .or_insert_with_key(|path| DebugInfo::load(path));
+        let Some((file, line)) = debug_info
+            .as_ref()
+            .and_then(|debug_info| debug_info.location(offset))
+        else {
+            continue;
+        };
+        *lines
+            .entry((symbol_name, file.to_owned(), line))
+            .or_default() += statistics;
+    }
+
+    let mut lines: Vec<Line> = lines
+        .into_iter()
+        .map(|((symbol_name, file, line), statistics)| Line {
+            symbol_name,
+            file,
+            line,
+            statistics,
+        })
+        .collect();
+    lines.sort_unstable_by(|lhs, rhs| {
+        (&lhs.file, lhs.line, &lhs.symbol_name).cmp(&(&rhs.file, rhs.line, &rhs.symbol_name))
+    });
+    lines
+}
@@ -0,0 +1,194 @@
+//! Minimal reader for 64-bit little-endian ELF files. Only reads what is needed to attribute
+//! addresses to symbols: loadable segments and function symbols (from `.symtab` and `.dynsym`)
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const PT_LOAD: u32 = 1;
+const STT_FUNC: u8 = 2;
+const STT_GNU_IFUNC: u8 = 10;
+
+#[derive(Debug)]
+pub struct Segment {
+    pub virtual_address: u64,
+    pub offset: u64,
+    pub file_size: u64,
+}
+
+#[derive(Debug)]
+pub struct Section<'a> {
+    pub name: &'a str,
+    pub address: u64,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// Virtual address (as in the file, not where it is loaded)
+    pub address: u64,
+    /// May be zero if unknown
+    pub size: u64,
+    /// Mangled
+    pub name: String,
+}
+
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    pub segments: Vec<Segment>,
+    section_headers: Vec<SectionHeader>,
+    section_names: u16,
+}
+
+#[derive(Debug)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    entry_size: u64,
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn read_str(data: &[u8], at: usize) -> Option<&str> {
+    let rest = data.get(at..)?;
+    let end = rest.iter().position(|byte| *byte == 0)?;
+    str::from_utf8(&rest[..end]).ok()
+}
+
+impl<'a> ElfFile<'a> {
+    /// Returns `None` if not a (supported) ELF file
+    #[must_use]
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        // ELFCLASS64, ELFDATA2LSB
+        if data.get(..6)? != b"\x7fELF\x02\x01" {
+            return None;
+        }
+
+        let program_headers_offset = read_u64(data, 0x20)? as usize;
+        let section_headers_offset = read_u64(data, 0x28)? as usize;
+        let program_header_size = read_u16(data, 0x36)? as usize;
+        let program_header_count = read_u16(data, 0x38)? as usize;
+        let section_header_size = read_u16(data, 0x3A)? as usize;
+        let section_header_count = read_u16(data, 0x3C)? as usize;
+        let section_names = read_u16(data, 0x3E)?;
+
+        let mut segments = Vec::new();
+        for idx in 0..program_header_count {
+            let at = program_headers_offset + idx * program_header_size;
+            if read_u32(data, at)? == PT_LOAD {
+                segments.push(Segment {
+                    offset: read_u64(data, at + 0x08)?,
+                    virtual_address: read_u64(data, at + 0x10)?,
+                    file_size: read_u64(data, at + 0x20)?,
+                });
+            }
+        }
+
+        let mut section_headers = Vec::new();
+        for idx in 0..section_header_count {
+            let at = section_headers_offset + idx * section_header_size;
+            section_headers.push(SectionHeader {
+                name: read_u32(data, at)?,
+                kind: read_u32(data, at + 0x04)?,
+                address: read_u64(data, at + 0x10)?,
+                offset: read_u64(data, at + 0x18)?,
+                size: read_u64(data, at + 0x20)?,
+                link: read_u32(data, at + 0x28)?,
+                entry_size: read_u64(data, at + 0x38)?,
+            });
+        }
+
+        Some(Self {
+            data,
+            segments,
+            section_headers,
+            section_names,
+        })
+    }
+
+    fn section_data(&self, header: &SectionHeader) -> &'a [u8] {
+        let start = header.offset as usize;
+        let end = start.saturating_add(header.size as usize);
+        self.data.get(start..end).unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn section(&self, name: &str) -> Option<Section<'a>> {
+        let names = self.section_data(self.section_headers.get(self.section_names as usize)?);
+        self.section_headers.iter().find_map(|header| {
+            (read_str(names, header.name as usize)? == name).then(|| Section {
+                name: read_str(names, header.name as usize).unwrap(),
+                address: header.address,
+                data: self.section_data(header),
+            })
+        })
+    }
+
+    /// Function symbols from `.symtab` and `.dynsym`, sorted by address and deduplicated
+    #[must_use]
+    pub fn function_symbols(&self) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        for header in &self.section_headers {
+            if !matches!(header.kind, SHT_SYMTAB | SHT_DYNSYM) || header.entry_size == 0 {
+                continue;
+            }
+            let table = self.section_data(header);
+            let Some(strings) = self.section_headers.get(header.link as usize) else {
+                continue;
+            };
+            let strings = self.section_data(strings);
+
+            for entry in table.chunks_exact(header.entry_size as usize) {
+                let (Some(name), Some(address), Some(size)) = (
+                    read_u32(entry, 0),
+                    read_u64(entry, 0x08),
+                    read_u64(entry, 0x10),
+                ) else {
+                    continue;
+                };
+                let kind = entry[4] & 0xf;
+                if !matches!(kind, STT_FUNC | STT_GNU_IFUNC) || address == 0 {
+                    continue;
+                }
+                let Some(name) = read_str(strings, name as usize) else {
+                    continue;
+                };
+                symbols.push(Symbol {
+                    address,
+                    size,
+                    name: name.to_owned(),
+                });
+            }
+        }
+        symbols.sort_unstable_by(|lhs, rhs| {
+            lhs.address
+                .cmp(&rhs.address)
+                .then_with(|| rhs.size.cmp(&lhs.size))
+        });
+        symbols.dedup_by_key(|symbol| symbol.address);
+        symbols
+    }
+
+    /// Converts an offset into the file into the virtual address it is loaded at (before
+    /// relocation)
+    #[must_use]
+    pub fn offset_to_virtual_address(&self, offset: u64) -> Option<u64> {
+        self.segments.iter().find_map(|segment| {
+            (segment.offset..segment.offset + segment.file_size)
+                .contains(&offset)
+                .then(|| offset - segment.offset + segment.virtual_address)
+        })
+    }
+}
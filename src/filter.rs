@@ -0,0 +1,269 @@
+//! `--include`, `--exclude` and `--merge-internals`. Symbols which are filtered out are folded into
+//! a named bucket rather than dropped, so totals still add up
+
+/// Folded into with `--merge-internals`
+pub const INTERNAL: &str = "Internal";
+/// Folded into when not matching any `--include` pattern
+pub const OTHER: &str = "[other]";
+/// Folded into when matching an `--exclude` pattern (which is not a preset)
+pub const EXCLUDED: &str = "[excluded]";
+
+/// Named sets of symbols, with the bucket their symbols are folded into
+const PRESETS: &[(&str, &str, Pattern)] = &[
+    (
+        "internal",
+        INTERNAL,
+        Pattern::Prefixes(&["std::", "core::", "alloc::", "_", "*", "OUTLINED_FUNCTION_"]),
+    ),
+    (
+        "rust-std",
+        "[rust-std]",
+        Pattern::Prefixes(&[
+            "std::",
+            "core::",
+            "alloc::",
+            "<std::",
+            "<core::",
+            "<alloc::",
+            "__rust",
+            "__rdl_",
+            "__rg_",
+            "rust_begin_unwind",
+            "rust_panic",
+        ]),
+    ),
+    (
+        "libc",
+        "[libc]",
+        Pattern::CSymbols {
+            prefixes: &[
+                "[libc.so",
+                "[libm.so",
+                "[libpthread",
+                "[ld-linux",
+                "[libgcc_s",
+                "__libc_",
+                "__GI_",
+                "_IO_",
+                "_dl_",
+                "pthread_",
+                "__pthread_",
+            ],
+            names: &[
+                "malloc",
+                "calloc",
+                "realloc",
+                "free",
+                "memcpy",
+                "memmove",
+                "memset",
+                "memcmp",
+                "memchr",
+                "memrchr",
+                "rawmemchr",
+                "mempcpy",
+                "strlen",
+                "strnlen",
+                "strcmp",
+                "strncmp",
+                "strchr",
+                "strrchr",
+                "strchrnul",
+                "strcpy",
+                "strncpy",
+                "stpcpy",
+                "strcat",
+                "strstr",
+                "strspn",
+                "strcspn",
+                "strpbrk",
+                "strcasecmp",
+                "strncasecmp",
+                "wmemset",
+                "wmemchr",
+                "wmemcmp",
+                "wcslen",
+                "wcsnlen",
+                "wcscmp",
+                "wcschr",
+                "wcscpy",
+                "strcasecmp_l",
+                "strncasecmp_l",
+                "strtol",
+                "strtoul",
+                "strtoll",
+                "strtoull",
+                "strtod",
+            ],
+        },
+    ),
+];
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    /// `*` matches any sequence and `?` any character
+    Glob(String),
+    /// From a preset
+    Prefixes(&'static [&'static str]),
+    /// C symbols (without `::`) from a preset, starting with one of `prefixes` or named one of
+    /// `names`. Names can have leading underscores and the suffix of an optimised variant
+    /// (`__memmove_avx_unaligned`)
+    CSymbols {
+        prefixes: &'static [&'static str],
+        names: &'static [&'static str],
+    },
+}
+
+impl Pattern {
+    fn matches(&self, symbol_name: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob_matches(glob, symbol_name),
+            Self::Prefixes(prefixes) => prefixes
+                .iter()
+                .any(|prefix| symbol_name.starts_with(prefix)),
+            Self::CSymbols { prefixes, names } => {
+                if symbol_name.contains("::") {
+                    return false;
+                }
+                let base = symbol_name.trim_start_matches('_');
+                prefixes
+                    .iter()
+                    .any(|prefix| symbol_name.starts_with(prefix))
+                    || names.iter().any(|name| {
+                        base == *name
+                            || (symbol_name.starts_with("__")
+                                && base
+                                    .strip_prefix(name)
+                                    .is_some_and(|suffix| suffix.starts_with('_')))
+                    })
+            }
+        }
+    }
+
+    /// A preset name or a glob. Also the bucket for excluded symbols
+    fn parse(on: &str) -> (Self, &'static str) {
+        match PRESETS.iter().find(|(name, _, _)| *name == on) {
+            Some((_, bucket, pattern)) => (pattern.clone(), bucket),
+            None => (Self::Glob(on.to_owned()), EXCLUDED),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    /// With the bucket symbols are folded into
+    exclude: Vec<(Pattern, &'static str)>,
+}
+
+impl Filter {
+    /// Only keep symbols matching the glob or preset (and any others included)
+    pub fn include(&mut self, pattern: &str) {
+        self.include.push(Pattern::parse(pattern).0);
+    }
+
+    /// Fold symbols matching the glob or preset into a bucket. Presets have their own bucket
+    pub fn exclude(&mut self, pattern: &str) {
+        self.exclude.push(Pattern::parse(pattern));
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// The bucket a (demangled) symbol is folded into, or `None` if it is kept
+    #[must_use]
+    pub fn bucket(&self, symbol_name: &str) -> Option<&'static str> {
+        if let Some((_, bucket)) = self
+            .exclude
+            .iter()
+            .find(|(pattern, _)| pattern.matches(symbol_name))
+        {
+            Some(bucket)
+        } else if !self.include.is_empty()
+            && !self
+                .include
+                .iter()
+                .any(|pattern| pattern.matches(symbol_name))
+        {
+            Some(OTHER)
+        } else {
+            None
+        }
+    }
+
+    /// The bucket or the symbol itself
+    #[must_use]
+    pub fn name<'a>(&self, symbol_name: &'a str) -> &'a str {
+        self.bucket(symbol_name).unwrap_or(symbol_name)
+    }
+
+    /// Whether a name is a bucket. Inclusive counts of the symbols in it overlap, so they cannot
+    /// be summed
+    #[must_use]
+    pub fn is_bucket(&self, name: &str) -> bool {
+        (!self.include.is_empty() && name == OTHER)
+            || self.exclude.iter().any(|(_, bucket)| *bucket == name)
+    }
+}
+
+fn glob_matches(glob: &str, on: &str) -> bool {
+    let (glob, on): (Vec<char>, Vec<char>) = (glob.chars().collect(), on.chars().collect());
+    let (mut g, mut o) = (0, 0);
+    // Position after the last `*` and where it started matching, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+    while o < on.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g + 1, o));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == on[o] => {
+                g += 1;
+                o += 1;
+            }
+            _ => match star {
+                Some((after, start)) => {
+                    g = after;
+                    o = start + 1;
+                    star = Some((after, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+
+    #[test]
+    fn libc_preset_matches_whole_c_names() {
+        let mut filter = Filter::default();
+        filter.exclude("libc");
+        for symbol in [
+            "memcpy",
+            "__memmove_avx_unaligned",
+            "__strlen_avx2",
+            "free",
+            "__libc_malloc",
+            "[libc.so.6]",
+        ] {
+            assert_eq!(filter.bucket(symbol), Some("[libc]"), "{symbol}");
+        }
+        for symbol in [
+            "memchr::memchr::fallback::memchr",
+            "structopt::clap::App::new",
+            "strsim::levenshtein",
+            "stream::next",
+            "freelist::push",
+            "memory_pool",
+            "strategy",
+        ] {
+            assert_eq!(filter.bucket(symbol), None, "{symbol}");
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Folded stacks (as used by Brendan Gregg's `flamegraph.pl` and `inferno`) and a standalone
+//! SVG flamegraph renderer. Stacks are weighted by instruction count
+
+use crate::Stack;
+
+const WIDTH: f64 = 1200.;
+const FRAME_HEIGHT: f64 = 16.;
+const TOP: f64 = 36.;
+const BOTTOM: f64 = 8.;
+/// Approximate width of a character at the font size used
+const CHAR_WIDTH: f64 = 7.;
+/// Frames narrower than this are not drawn
+const MIN_WIDTH: f64 = 0.1;
+
+/// `;` separates frames, so it is replaced in names (which happens for array types, `[u8; 4]`)
+fn frame_name(name: &str) -> std::borrow::Cow<'_, str> {
+    if name.contains(';') {
+        std::borrow::Cow::Owned(name.replace(';', ","))
+    } else {
+        std::borrow::Cow::Borrowed(name)
+    }
+}
+
+/// One line per stack, `outer;inner count`
+#[must_use]
+pub fn folded(stacks: &[Stack]) -> String {
+    let mut lines: Vec<String> = stacks
+        .iter()
+        .filter(|stack| stack.statistics.total != 0)
+        .map(|stack| {
+            let frames: Vec<_> = stack.frames.iter().map(|name| frame_name(name)).collect();
+            format!(
+                "{frames} {count}",
+                frames = frames.join(";"),
+                count = stack.statistics.total
+            )
+        })
+        .collect();
+    lines.sort_unstable();
+    let mut buf = lines.join("\n");
+    buf.push('\n');
+    buf
+}
+
+#[derive(Default)]
+struct Node<'a> {
+    name: &'a str,
+    /// Including children
+    value: u64,
+    children: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn add(&mut self, frames: &'a [String], count: u64) {
+        self.value = crate::checked_add("total", self.value, count);
+        let Some((first, rest)) = frames.split_first() else {
+            return;
+        };
+        let child = match self.children.iter().position(|child| child.name == first) {
+            Some(idx) => &mut self.children[idx],
+            None => {
+                self.children.push(Node {
+                    name: first,
+                    ..Node::default()
+                });
+                self.children.last_mut().unwrap()
+            }
+        };
+        child.add(rest, count);
+    }
+
+    fn depth(&self) -> usize {
+        1 + self.children.iter().map(Node::depth).max().unwrap_or(0)
+    }
+}
+
+fn escape_xml(on: &str) -> String {
+    on.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Deterministic warm colour from the name
+fn colour(name: &str) -> (u8, u8, u8) {
+    let mut hash: u32 = 2166136261;
+    for byte in name.bytes() {
+        hash = (hash ^ u32::from(byte)).wrapping_mul(16777619);
+    }
+    let [a, b, c, _] = hash.to_le_bytes();
+    (
+        205 + a % 50,
+        (f64::from(b) / 255. * 230.) as u8,
+        (f64::from(c) / 255. * 55.) as u8,
+    )
+}
+
+/// Label which fits in `width`, truncated with `..`
+fn label(name: &str, width: f64) -> String {
+    let chars = ((width - 6.) / CHAR_WIDTH) as usize;
+    if chars < 3 {
+        String::new()
+    } else if name.chars().count() <= chars {
+        name.to_owned()
+    } else {
+        let mut label: String = name.chars().take(chars - 2).collect();
+        label.push_str("..");
+        label
+    }
+}
+
+/// Interactive (click to zoom) flamegraph. Does not need any external scripts
+#[must_use]
+pub fn svg(stacks: &[Stack], title: &str) -> String {
+    use std::fmt::Write;
+
+    let mut root = Node {
+        name: "all",
+        ..Node::default()
+    };
+    for stack in stacks {
+        root.add(&stack.frames, stack.statistics.total);
+    }
+    let height = TOP + root.depth() as f64 * FRAME_HEIGHT + BOTTOM;
+
+    let mut buf = String::new();
+    let _ = write!(
+        buf,
+        r##"<?xml version="1.0" standalone="no"?>
+<svg version="1.1" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" xmlns="http://www.w3.org/2000/svg" font-family="monospace" font-size="12">
+<style>.f:hover rect {{ stroke: black; stroke-width: 0.5; cursor: pointer; }} text {{ pointer-events: none; }}</style>
+<rect x="0" y="0" width="{WIDTH}" height="{height}" fill="#f8f8f8"/>
+<text x="{center}" y="20" text-anchor="middle" font-size="16">{title}</text>
+<text id="details" x="10" y="{details}"> </text>
+"##,
+        center = WIDTH / 2.,
+        title = escape_xml(title),
+        details = height - 2.,
+    );
+
+    // Depth first. `x` and `width` are fractions of the root so the script can rescale them
+    let mut queue = vec![(&root, 0usize, 0f64)];
+    while let Some((node, depth, x)) = queue.pop() {
+        let fraction = node.value as f64 / root.value.max(1) as f64;
+        let width = fraction * WIDTH;
+        if width < MIN_WIDTH {
+            continue;
+        }
+        let y = height - BOTTOM - (depth + 1) as f64 * FRAME_HEIGHT;
+        let (r, g, b) = colour(node.name);
+        let name = escape_xml(node.name);
+        let _ = writeln!(
+            buf,
+            r#"<g class="f" data-x="{x}" data-w="{fraction}" data-d="{depth}" onclick="zoom(this)"><title>{name} ({value} instructions, {percentage:.2}%)</title><rect x="{rx:.2}" y="{y}" width="{width:.2}" height="{rh}" fill="rgb({r},{g},{b})" rx="2"/><text x="{tx:.2}" y="{ty}">{label}</text></g>"#,
+            value = node.value,
+            percentage = fraction * 100.,
+            rx = x * WIDTH,
+            rh = FRAME_HEIGHT - 1.,
+            tx = x * WIDTH + 3.,
+            ty = y + FRAME_HEIGHT - 4.,
+            label = escape_xml(&label(node.name, width)),
+        );
+
+        let mut child_x = x;
+        let mut children: Vec<_> = node.children.iter().collect();
+        children.sort_unstable_by_key(|child| child.name);
+        for child in children {
+            queue.push((child, depth + 1, child_x));
+            child_x += child.value as f64 / root.value.max(1) as f64;
+        }
+    }
+
+    let _ = write!(
+        buf,
+        r#"<script><![CDATA[
+const WIDTH = {WIDTH}, CHAR_WIDTH = {CHAR_WIDTH};
+function label(name, width) {{
+    const chars = Math.floor((width - 6) / CHAR_WIDTH);
+    if (chars < 3) return "";
+    return name.length <= chars ? name : name.slice(0, chars - 2) + "..";
+}}
+function zoom(target) {{
+    const zx = parseFloat(target.dataset.x), zw = parseFloat(target.dataset.w), zd = parseInt(target.dataset.d);
+    for (const frame of document.querySelectorAll(".f")) {{
+        const x = parseFloat(frame.dataset.x), w = parseFloat(frame.dataset.w), d = parseInt(frame.dataset.d);
+        const rect = frame.querySelector("rect"), text = frame.querySelector("text");
+        const name = frame.querySelector("title").textContent.replace(/ \([^(]*\)$/, "");
+        let nx, nw;
+        if (d < zd && x <= zx && zx + zw <= x + w + 1e-9) {{
+            // Ancestor
+            nx = 0; nw = WIDTH; frame.style.opacity = 0.6;
+        }} else if (d >= zd && zx <= x && x + w <= zx + zw + 1e-9) {{
+            nx = (x - zx) / zw * WIDTH; nw = w / zw * WIDTH; frame.style.opacity = 1;
+        }} else {{
+            frame.style.display = "none";
+            continue;
+        }}
+        frame.style.display = "";
+        rect.setAttribute("x", nx); rect.setAttribute("width", nw);
+        text.setAttribute("x", nx + 3); text.textContent = label(name, nw);
+    }}
+}}
+for (const frame of document.querySelectorAll(".f")) {{
+    frame.addEventListener("mouseover", () => document.getElementById("details").textContent = frame.querySelector("title").textContent);
+}}
+]]></script>
+</svg>
+"#
+    );
+    buf
+}
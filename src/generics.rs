@@ -0,0 +1,191 @@
+//! `--merge-generics`. Sums the monomorphised instantiations of generic functions into one entry
+
+use crate::{Entry, Line, Statistics, ToolOutput};
+
+use std::collections::HashMap;
+
+/// Instantiations of a generic function which were merged
+#[derive(Clone, Debug)]
+pub struct Generic {
+    /// Without generic arguments
+    pub symbol_name: String,
+    /// Number of instantiations. `instantiations` may be truncated to the highest
+    pub count: usize,
+    /// By total, highest first
+    pub instantiations: Vec<(String, Statistics)>,
+}
+
+/// Removes generic arguments and hashes from a (demangled) symbol name.
+/// `<alloc::vec::Vec<u8> as core::clone::Clone>::clone::h0123456789abcdef` becomes
+/// `<alloc::vec::Vec as core::clone::Clone>::clone`. Qualified paths (`<T as Trait>`) are kept
+#[must_use]
+pub fn normalise(symbol_name: &str) -> String {
+    let symbol_name = strip_hash(symbol_name);
+    let mut normalised = String::with_capacity(symbol_name.len());
+    let mut chars = symbol_name.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        // Qualified paths start the name (or another qualified path). `<impl T>` is a segment
+        let is_path = normalised.is_empty()
+            || normalised.ends_with('<')
+            || (normalised.ends_with("::") && symbol_name[idx..].starts_with("<impl "));
+        if c == '<' && !is_path {
+            // `::<` (turbofish)
+            if let Some(without) = normalised.strip_suffix("::") {
+                normalised.truncate(without.len());
+            }
+            let mut depth = 1usize;
+            let mut previous = c;
+            for (_, c) in chars.by_ref() {
+                match c {
+                    '<' => depth += 1,
+                    // `->` in function pointer types
+                    '>' if previous != '-' => depth -= 1,
+                    _ => {}
+                }
+                previous = c;
+                if depth == 0 {
+                    break;
+                }
+            }
+        } else {
+            normalised.push(c);
+        }
+    }
+    normalised
+}
+
+/// Removes the `::h0123456789abcdef` suffix of legacy mangled names
+fn strip_hash(symbol_name: &str) -> &str {
+    match symbol_name.rsplit_once("::h") {
+        Some((name, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            name
+        }
+        _ => symbol_name,
+    }
+}
+
+/// Merges instantiations everywhere in the results. Returns the instantiations which were merged
+/// in the whole program results, by total of the merged entry
+pub fn merge(output: &mut ToolOutput) -> Vec<Generic> {
+    let ToolOutput::SymbolInstructionCounts {
+        symbols,
+        regions,
+        calls,
+        stacks,
+        lines,
+        threads,
+        ..
+    } = output
+    else {
+        return Vec::new();
+    };
+
+    let generics = merge_entries(symbols);
+    for region in regions {
+        merge_entries(&mut region.symbols);
+    }
+    for thread in threads {
+        merge_entries(&mut thread.symbols);
+    }
+
+    let mut merged_calls: Vec<crate::Call> = Vec::with_capacity(calls.len());
+    for mut call in calls.drain(..) {
+        call.caller = normalise(&call.caller);
+        call.callee = normalise(&call.callee);
+        match merged_calls
+            .iter_mut()
+            .find(|existing| existing.caller == call.caller && existing.callee == call.callee)
+        {
+            Some(existing) => {
+                existing.count = crate::checked_add("call count", existing.count, call.count);
+                existing.inclusive += call.inclusive;
+            }
+            None => merged_calls.push(call),
+        }
+    }
+    *calls = merged_calls;
+
+    // Identical stacks are summed when they are written
+    for stack in stacks {
+        for frame in &mut stack.frames {
+            *frame = normalise(frame);
+        }
+    }
+
+    let mut merged_lines: HashMap<(String, String, u32), Statistics> = HashMap::new();
+    for line in lines.drain(..) {
+        *merged_lines
+            .entry((normalise(&line.symbol_name), line.file, line.line))
+            .or_default() += line.statistics;
+    }
+    lines.extend(
+        merged_lines
+            .into_iter()
+            .map(|((symbol_name, file, line), statistics)| Line {
+                symbol_name,
+                file,
+                line,
+                statistics,
+            }),
+    );
+    lines.sort_unstable_by(|lhs, rhs| {
+        (&lhs.file, lhs.line, &lhs.symbol_name).cmp(&(&rhs.file, rhs.line, &rhs.symbol_name))
+    });
+
+    generics
+}
+
+/// Inclusive counts are summed, so they overlap where one instantiation calls another
+fn merge_entries(entries: &mut Vec<Entry>) -> Vec<Generic> {
+    let mut merged: Vec<Entry> = Vec::with_capacity(entries.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut instantiations: HashMap<String, Vec<(String, Statistics)>> = HashMap::new();
+    for entry in entries.drain(..) {
+        let name = normalise(&entry.symbol_name);
+        instantiations
+            .entry(name.clone())
+            .or_default()
+            .push((entry.symbol_name.clone(), entry.statistics.clone()));
+        match positions.get(&name) {
+            Some(&position) => {
+                let existing = &mut merged[position];
+                existing.statistics += entry.statistics;
+                if let (Some(into), Some(from)) = (&mut existing.inclusive, entry.inclusive) {
+                    *into += from;
+                }
+            }
+            None => {
+                positions.insert(name.clone(), merged.len());
+                merged.push(Entry {
+                    symbol_name: name,
+                    ..entry
+                });
+            }
+        }
+    }
+    *entries = merged;
+
+    let mut generics: Vec<Generic> = instantiations
+        .into_iter()
+        .filter(|(name, instantiations)| instantiations.len() > 1 || instantiations[0].0 != *name)
+        .map(|(symbol_name, mut instantiations)| {
+            instantiations
+                .sort_unstable_by_key(|(_, statistics)| std::cmp::Reverse(statistics.total));
+            Generic {
+                symbol_name,
+                count: instantiations.len(),
+                instantiations,
+            }
+        })
+        .collect();
+    generics.sort_unstable_by_key(|generic| {
+        std::cmp::Reverse(
+            generic
+                .instantiations
+                .iter()
+                .map(|(_, statistics)| statistics.total)
+                .fold(0, |sum, total| crate::checked_add("total", sum, total)),
+        )
+    });
+    generics
+}
@@ -0,0 +1,399 @@
+//! Aggregating symbols by the crate, module, type or trait in their (demangled) path
+
+use crate::{Entry, Statistics};
+
+/// `--group-by`
+#[derive(Clone, Copy, Debug)]
+pub enum GroupBy {
+    Crate,
+    /// Nested modules
+    Module,
+    /// Modules then every function in them
+    Function,
+    /// Modules then the type of the `impl` block
+    Impl,
+    /// The implemented trait then the implementing type
+    Trait,
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(on: &str) -> Result<Self, Self::Err> {
+        match on {
+            "crate" => Ok(Self::Crate),
+            "module" => Ok(Self::Module),
+            "function" => Ok(Self::Function),
+            "impl" => Ok(Self::Impl),
+            "trait" => Ok(Self::Trait),
+            on => Err(format!(
+                "unknown group {on:?}, expected 'crate', 'module', 'function', 'impl' or 'trait'"
+            )),
+        }
+    }
+}
+
+/// A path prefix and everything executed under it
+#[derive(Clone, Debug, Default)]
+pub struct Group {
+    pub name: String,
+    pub statistics: Statistics,
+    /// Sorted by total, highest first
+    pub children: Vec<Group>,
+}
+
+/// For symbols not in a trait `impl` with [`GroupBy::Trait`]
+pub const NO_TRAIT: &str = "[no trait]";
+/// For methods of types without a path (`usize`, `[T]` or a generic parameter) which are not in a
+/// module or trait with one
+pub const PRIMITIVE: &str = "[primitive]";
+
+/// Builds the tree of groups from symbols. Only exclusive counts are summed, as the inclusive
+/// counts of a function and its callees overlap
+#[must_use]
+pub fn group(symbols: &[Entry], by: GroupBy) -> Vec<Group> {
+    let mut root = Group::default();
+    for entry in symbols {
+        let path = Path::parse(&entry.symbol_name).groups(by);
+        let mut group = &mut root;
+        for name in path {
+            let position = group.children.iter().position(|child| child.name == name);
+            let position = position.unwrap_or_else(|| {
+                group.children.push(Group {
+                    name,
+                    ..Group::default()
+                });
+                group.children.len() - 1
+            });
+            group = &mut group.children[position];
+            group.statistics += entry.statistics.clone();
+        }
+    }
+    sort(&mut root.children);
+    root.children
+}
+
+fn sort(groups: &mut [Group]) {
+    groups.sort_unstable_by(|lhs, rhs| {
+        rhs.statistics
+            .total
+            .cmp(&lhs.statistics.total)
+            .then_with(|| lhs.name.cmp(&rhs.name))
+    });
+    for group in groups {
+        sort(&mut group.children);
+    }
+}
+
+/// A symbol split into its parts. `<serde_json::Value as core::fmt::Display>::fmt` has modules
+/// `serde_json`, type `Value`, trait `core::fmt::Display` and function `fmt`
+#[derive(Debug, PartialEq)]
+struct Path<'a> {
+    modules: Vec<&'a str>,
+    /// Without generic arguments
+    r#type: Option<&'a str>,
+    /// The full self type of an `impl` (without generic arguments), for [`GroupBy::Trait`]
+    implementor: Option<&'a str>,
+    r#trait: Option<Vec<&'a str>>,
+    function: Vec<&'a str>,
+}
+
+impl<'a> Path<'a> {
+    fn parse(symbol: &'a str) -> Self {
+        let mut segments = split_segments(symbol);
+        let qualified = segments
+            .first()
+            .and_then(|first| first.strip_prefix('<')?.strip_suffix('>'))
+            .filter(|qualified| !qualified.starts_with("impl "));
+        if let Some(qualified) = qualified {
+            // `<Type as Trait>::function` or `<Type>::function`
+            let function = segments.split_off(1);
+            let (self_type, r#trait) = match split_top_level(qualified, " as ") {
+                Some((self_type, r#trait)) => (self_type, Some(split_segments(r#trait))),
+                None => (qualified, None),
+            };
+            return Self::with_impl(self_type, r#trait, Vec::new(), function);
+        }
+
+        // `module::<impl Type>::function` or `module::<impl Trait for Type>::function`
+        let impl_position = segments
+            .iter()
+            .position(|segment| segment.starts_with("<impl ") && segment.ends_with('>'));
+        if let Some(position) = impl_position {
+            let function = segments.split_off(position + 1);
+            let block = &segments.pop().unwrap()["<impl ".len()..];
+            let block = &block[..block.len() - 1];
+            let (self_type, r#trait) = match split_top_level(block, " for ") {
+                Some((r#trait, self_type)) => (self_type, Some(split_segments(r#trait))),
+                None => (block, None),
+            };
+            return Self::with_impl(self_type, r#trait, segments, function);
+        }
+
+        // Types (in inherent impls, in older manglings) start with an uppercase letter
+        let type_position = segments
+            .iter()
+            .skip(1)
+            .position(|segment| segment.starts_with(|c: char| c.is_ascii_uppercase()))
+            .map(|position| position + 1);
+        match type_position {
+            Some(position) if position + 1 < segments.len() => {
+                let function = segments.split_off(position + 1);
+                let r#type = segments.pop();
+                Self {
+                    modules: segments,
+                    r#type,
+                    implementor: None,
+                    r#trait: None,
+                    function,
+                }
+            }
+            _ => {
+                let function = segments.split_off(segments.len().saturating_sub(1));
+                Self {
+                    modules: segments,
+                    r#type: None,
+                    implementor: None,
+                    r#trait: None,
+                    function,
+                }
+            }
+        }
+    }
+
+    /// Methods are grouped under the path of their type. Types without one (primitives and generic
+    /// parameters) fall back to the module of the `impl` block, then the module of the trait
+    fn with_impl(
+        self_type: &'a str,
+        r#trait: Option<Vec<&'a str>>,
+        enclosing: Vec<&'a str>,
+        function: Vec<&'a str>,
+    ) -> Self {
+        let mut self_type = self_type;
+        for prefix in ["&mut ", "&", "*const ", "*mut ", "dyn "] {
+            self_type = self_type.strip_prefix(prefix).unwrap_or(self_type);
+        }
+        let implementor = strip_generic_arguments(self_type);
+        let mut type_path = split_segments(implementor);
+        let r#type = type_path.pop();
+        let modules = if !type_path.is_empty() {
+            type_path
+        } else if !enclosing.is_empty() {
+            enclosing
+        } else {
+            match r#trait.as_deref() {
+                Some([modules @ .., _]) if !modules.is_empty() => modules.to_vec(),
+                _ => vec![PRIMITIVE],
+            }
+        };
+        Self {
+            modules,
+            r#type,
+            implementor: Some(implementor),
+            r#trait,
+            function,
+        }
+    }
+
+    /// Names of the groups the symbol is in, outermost first
+    fn groups(self, by: GroupBy) -> Vec<String> {
+        // Symbols without modules (`main`, `[libc.so.6]`) are their own crate
+        let crate_name = self
+            .modules
+            .first()
+            .or(self.r#type.as_ref())
+            .or(self.function.first())
+            .copied()
+            .unwrap_or_default();
+        let modules = || -> Vec<String> {
+            if self.modules.is_empty() {
+                vec![crate_name.to_owned()]
+            } else {
+                self.modules
+                    .iter()
+                    .map(|&module| module.to_owned())
+                    .collect()
+            }
+        };
+        match by {
+            GroupBy::Crate => vec![crate_name.to_owned()],
+            GroupBy::Module => modules(),
+            GroupBy::Impl => {
+                let mut groups = modules();
+                if let Some(r#type) = self.r#type
+                    && !self.modules.is_empty()
+                {
+                    groups.push(r#type.to_owned());
+                }
+                groups
+            }
+            GroupBy::Function => {
+                let mut groups: Vec<String> = self
+                    .modules
+                    .iter()
+                    .map(|&module| module.to_owned())
+                    .collect();
+                groups.extend(self.r#type.map(str::to_owned));
+                let mut function = self.function.iter();
+                if let Some(first) = function.next() {
+                    // Trait methods are named with their trait, so they are distinct from
+                    // inherent methods of the same name
+                    match self.r#trait.as_ref().and_then(|r#trait| r#trait.last()) {
+                        Some(r#trait) => groups.push(format!("<impl {trait}>::{first}")),
+                        None => groups.push((*first).to_owned()),
+                    }
+                }
+                groups.extend(function.map(|&segment| segment.to_owned()));
+                groups
+            }
+            GroupBy::Trait => match self.r#trait {
+                Some(r#trait) => {
+                    let mut groups: Vec<String> =
+                        r#trait.iter().map(|&segment| segment.to_owned()).collect();
+                    groups.push(self.implementor.unwrap_or_default().to_owned());
+                    groups
+                }
+                None => vec![NO_TRAIT.to_owned()],
+            },
+        }
+    }
+}
+
+/// `alloc::vec::Vec<u8>` is `alloc::vec::Vec`
+fn strip_generic_arguments(r#type: &str) -> &str {
+    match r#type.find('<') {
+        Some(idx) if idx > 0 && r#type.ends_with('>') => &r#type[..idx],
+        _ => r#type,
+    }
+}
+
+/// Splits on `::` outside of generic arguments and qualified paths
+fn split_segments(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut rest = path;
+    while let Some((segment, after)) = split_top_level(rest, "::") {
+        segments.push(segment);
+        rest = after;
+    }
+    segments.push(rest);
+    segments
+}
+
+/// Splits at the first `separator` not inside `<>`, `()` or `[]`
+fn split_top_level<'a>(on: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let mut depth = 0usize;
+    for (idx, c) in on.char_indices() {
+        match c {
+            '<' | '(' | '[' | '{' => depth += 1,
+            // `->` in function pointer types
+            '>' if on[..idx].ends_with('-') => {}
+            '>' | ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ if depth == 0 && on[idx..].starts_with(separator) => {
+                return Some((&on[..idx], &on[idx + separator.len()..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupBy, Path};
+
+    fn groups(symbol: &str, by: GroupBy) -> Vec<String> {
+        Path::parse(symbol).groups(by)
+    }
+
+    #[test]
+    fn inherent_impl_of_primitive() {
+        let symbol = "core::num::<impl usize>::unchecked_add::precondition_check";
+        assert_eq!(groups(symbol, GroupBy::Crate), ["core"]);
+        assert_eq!(groups(symbol, GroupBy::Impl), ["core", "num", "usize"]);
+        assert_eq!(
+            groups(symbol, GroupBy::Function),
+            [
+                "core",
+                "num",
+                "usize",
+                "unchecked_add",
+                "precondition_check"
+            ]
+        );
+        assert_eq!(groups(symbol, GroupBy::Trait), [super::NO_TRAIT]);
+    }
+
+    #[test]
+    fn trait_impl_block() {
+        let symbol = "core::iter::range::<impl core::iter::traits::iterator::Iterator for core::ops::range::Range<A>>::next";
+        // Under the type's module, not the module of the `impl` block
+        assert_eq!(
+            groups(symbol, GroupBy::Impl),
+            ["core", "ops", "range", "Range"]
+        );
+        assert_eq!(
+            groups(symbol, GroupBy::Trait),
+            [
+                "core",
+                "iter",
+                "traits",
+                "iterator",
+                "Iterator",
+                "core::ops::range::Range"
+            ]
+        );
+    }
+
+    #[test]
+    fn qualified_path_with_primitive_self_type() {
+        let symbol = "<usize as core::iter::range::Step>::forward_unchecked";
+        assert_eq!(groups(symbol, GroupBy::Crate), ["core"]);
+        assert_eq!(
+            groups(symbol, GroupBy::Impl),
+            ["core", "iter", "range", "usize"]
+        );
+        assert_eq!(
+            groups(symbol, GroupBy::Trait),
+            ["core", "iter", "range", "Step", "usize"]
+        );
+    }
+
+    #[test]
+    fn qualified_path_with_generic_parameter() {
+        let symbol = "<I as core::iter::traits::collect::IntoIterator>::into_iter";
+        assert_eq!(groups(symbol, GroupBy::Crate), ["core"]);
+        // Without a path for the trait either
+        assert_eq!(
+            groups("<I as IntoIterator>::into_iter", GroupBy::Crate),
+            [super::PRIMITIVE]
+        );
+        assert_eq!(
+            groups("<[T]>::len", GroupBy::Impl),
+            [super::PRIMITIVE, "[T]"]
+        );
+    }
+
+    #[test]
+    fn qualified_path_with_type_path() {
+        let symbol = "<alloc::vec::Vec<u8> as core::clone::Clone>::clone";
+        assert_eq!(groups(symbol, GroupBy::Crate), ["alloc"]);
+        assert_eq!(groups(symbol, GroupBy::Impl), ["alloc", "vec", "Vec"]);
+        assert_eq!(
+            groups(symbol, GroupBy::Trait),
+            ["core", "clone", "Clone", "alloc::vec::Vec"]
+        );
+        assert_eq!(
+            groups(
+                "<&mut alloc::string::String as core::fmt::Write>::write_str",
+                GroupBy::Impl
+            ),
+            ["alloc", "string", "String"]
+        );
+    }
+
+    #[test]
+    fn plain_functions() {
+        assert_eq!(groups("iterations::main", GroupBy::Module), ["iterations"]);
+        assert_eq!(groups("[libc.so.6]", GroupBy::Crate), ["[libc.so.6]"]);
+    }
+}
@@ -1,8 +1,22 @@
+pub mod compare;
+pub mod cost;
+pub mod debug_info;
+pub mod elf;
+pub mod filter;
+pub mod flamegraph;
+pub mod generics;
+pub mod grouping;
+mod markers;
+pub mod pprof;
 pub mod tools;
 pub mod utilities;
 
+pub use markers::{
+    START_SSC_MARK, STOP_SSC_MARK, depict_region_enter, depict_region_exit, depict_start,
+    depict_stop, region, start, stop,
+};
+
 use std::borrow::Cow;
-use std::collections::HashMap;
 use std::ffi::OsStr;
 
 pub struct CommandRequest<'a> {
@@ -10,12 +24,70 @@ pub struct CommandRequest<'a> {
     pub arguments: Vec<Cow<'a, OsStr>>,
 }
 
-/// TODO iterations, etc for time etc
 pub struct ToolOptions {
-    /// Save SDE file...
+    /// Keep the SDE or QBDI results file at this path
     pub keep: Option<String>,
-    /// skip Rust internals
-    pub merge_internals: bool,
+    /// Symbols folded into buckets, from `--include`, `--exclude` and `--merge-internals`
+    pub filter: filter::Filter,
+    /// Number of measured runs (for `time`)
+    pub iterations: usize,
+    /// Number of discarded runs before measuring (for `time`)
+    pub warmup: usize,
+    /// Count kernel-mode hardware events as well as user-mode (for `perf-events`). Software events
+    /// always include the kernel
+    pub include_kernel: bool,
+    /// Only count between [`start`] and [`stop`]
+    pub region_of_interest: bool,
+    /// Also count each thread separately
+    pub per_thread: bool,
+    /// Report this many hottest basic blocks as [`ToolOutput::HotBlocks`] (for `sde`)
+    pub blocks: Option<usize>,
+    /// Attribute instructions in inlined functions to the (innermost) inlined function rather
+    /// than the function it is inlined into. Uses DWARF debug information
+    pub inline_frames: bool,
+    /// Kill the program if it runs for longer. For `time`, each run. SDE has a default
+    pub timeout: Option<std::time::Duration>,
+}
+
+/// How the benchmarked program finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Exited normally. `0` is success
+    Code(i32),
+    /// Terminated by a signal (for example `SIGSEGV` is 11 on Linux)
+    Signal(i32),
+    /// Killed after [`ToolOptions::timeout`]
+    TimedOut,
+}
+
+impl Exit {
+    pub const SUCCESS: Self = Self::Code(0);
+
+    #[must_use]
+    pub fn success(self) -> bool {
+        self == Self::SUCCESS
+    }
+}
+
+impl From<std::process::ExitStatus> for Exit {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return Self::Signal(signal);
+        }
+        // Always has a code on other platforms
+        Self::Code(status.code().unwrap_or(-1))
+    }
+}
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "exited with code {code}"),
+            Self::Signal(signal) => write!(f, "was terminated by signal {signal}"),
+            Self::TimedOut => write!(f, "timed out"),
+        }
+    }
 }
 
 #[non_exhaustive]
@@ -23,105 +95,353 @@ pub enum ToolOutput {
     SymbolInstructionCounts {
         symbols: Vec<Entry>,
         total: Statistics,
+        /// From [`region`]
+        regions: Vec<Region>,
+        /// Caller to callee edges. Empty if the tool does not track calls
+        calls: Vec<Call>,
+        /// Instructions executed in each call stack. Empty if the tool does not track calls
+        stacks: Vec<Stack>,
+        /// Instructions executed on each source line. Empty without debug information
+        lines: Vec<Line>,
+        /// Only with [`ToolOptions::per_thread`]
+        threads: Vec<Thread>,
+        exit: Exit,
+    },
+    Timings {
+        /// Measured runs, in the order they were run
+        runs: Vec<std::time::Duration>,
+        /// Number of runs discarded before measuring
+        warmup: usize,
+        statistics: tools::wall_clock::TimingStatistics,
+        /// Of the first failed run (runs stop there), otherwise success
+        exit: Exit,
+    },
+    #[cfg(target_os = "linux")]
+    Counters {
+        counters: Vec<tools::perf_events::Counter>,
+        exit: Exit,
     },
+    /// Most executed basic blocks, hottest first
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
+    HotBlocks {
+        blocks: Vec<tools::sde::Block>,
+        /// Instructions executed in the whole program
+        total: u64,
+        exit: Exit,
+    },
+}
+
+impl ToolOutput {
+    /// How the benchmarked program finished. Results of a failed program are incomplete
+    #[must_use]
+    pub fn exit(&self) -> Exit {
+        match self {
+            Self::SymbolInstructionCounts { exit, .. } | Self::Timings { exit, .. } => *exit,
+            #[cfg(target_os = "linux")]
+            Self::Counters { exit, .. } => *exit,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
+            Self::HotBlocks { exit, .. } => *exit,
+        }
+    }
+
+    /// Headline numbers, used for comparing runs against each other (e.g. in parameter sweeps).
+    /// Timings are in nanoseconds. `None` for unavailable values
+    #[must_use]
+    pub fn summary(&self, breakdown: bool) -> Vec<(Cow<'static, str>, Option<u64>)> {
+        match self {
+            Self::SymbolInstructionCounts { total, .. } => {
+                if breakdown {
+                    total
+                        .metrics()
+                        .map(|(name, value)| (Cow::Owned(name.to_owned()), Some(value)))
+                        .collect()
+                } else {
+                    vec![(Cow::Borrowed("total"), Some(total.total))]
+                }
+            }
+            Self::Timings { statistics, .. } => {
+                let rows = statistics.as_rows();
+                let rows: &[_] = if breakdown { &rows } else { &rows[..5] };
+                rows.iter()
+                    .map(|(name, value)| {
+                        (
+                            Cow::Owned(format!("{name}_ns")),
+                            Some(value.as_nanos() as u64),
+                        )
+                    })
+                    .collect()
+            }
+            #[cfg(target_os = "linux")]
+            Self::Counters { counters, .. } => counters
+                .iter()
+                .map(|counter| {
+                    let value = match counter.value {
+                        tools::perf_events::CounterValue::Counted { value, .. } => Some(value),
+                        tools::perf_events::CounterValue::Unavailable(_) => None,
+                    };
+                    (Cow::Borrowed(counter.name), value)
+                })
+                .collect(),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
+            Self::HotBlocks { total, .. } => {
+                vec![(Cow::Borrowed("total"), Some(*total))]
+            }
+        }
+    }
+}
+
+/// Instructions executed inside a [`region`]
+#[derive(Clone, Debug)]
+pub struct Region {
+    /// Path of names (`outer > inner`) for nested regions
+    pub name: String,
+    pub symbols: Vec<Entry>,
+    pub total: Statistics,
+}
+
+/// Instructions executed by one thread
+#[derive(Clone, Debug)]
+pub struct Thread {
+    /// In order of creation. The main thread is 0
+    pub index: usize,
+    pub symbols: Vec<Entry>,
+    pub total: Statistics,
+}
+
+/// Calls from one function to another
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub caller: String,
+    pub callee: String,
+    /// Number of calls
+    pub count: u64,
+    /// Instructions executed in the callee (and its callees) for these calls
+    pub inclusive: Statistics,
+}
+
+/// Instructions executed with a call stack
+#[derive(Clone, Debug)]
+pub struct Stack {
+    /// Outermost first
+    pub frames: Vec<String>,
+    pub statistics: Statistics,
+}
+
+/// Instructions executed on a line of source, found through DWARF line tables
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// The function the instructions are in. Inlined code is in the function it is inlined into,
+    /// unless [`ToolOptions::inline_frames`]
+    pub symbol_name: String,
+    pub file: String,
+    pub line: u32,
+    pub statistics: Statistics,
 }
 
+/// Separates region names in the path of nested regions
+pub const REGION_SEPARATOR: &str = " > ";
+
 /// TODO there more be more: branch, compare, return, etc
 #[derive(Clone, Debug)]
 pub struct Entry {
     pub symbol_name: String,
+    /// Instructions executed in the function itself
     pub statistics: Statistics,
+    /// Including instructions executed in functions it calls (directly or indirectly). Recursive
+    /// calls are only counted once. `None` if the tool does not track calls
+    pub inclusive: Option<Statistics>,
+}
+
+/// Metrics every instruction counting backend knows about, in the order they are shown. Backends
+/// add their own (SDE categories and ISA extensions, QBDI mnemonics), which are shown after these
+/// by name
+pub const METRICS: &[&str] = &[
+    "total",
+    "estimated_cycles",
+    "mem_read",
+    "mem_write",
+    "stack_read",
+    "stack_write",
+    "call",
+    "branch",
+    "return",
+    "compare",
+    "logic",
+    "arithmetic",
+];
+
+/// Where a metric is shown
+fn order(name: &str) -> (usize, &str) {
+    let known = METRICS.iter().position(|metric| *metric == name);
+    (known.unwrap_or(METRICS.len()), name)
 }
 
-/// Some copied from sde-output-parser
+/// Named counters. `total` is always present, other metrics once a backend adds them
 #[derive(Clone, Debug, Default)]
 pub struct Statistics {
-    pub total: u32,
-    pub mem_read: u32,
-    pub mem_write: u32,
-    pub stack_read: u32,
-    pub stack_write: u32,
-    pub call: u32,
-    pub branch: u32,
-    pub r#return: u32,
-    pub compare: u32,
-    pub logic: u32,
-    pub arithmetic: u32,
-    pub others: HashMap<String, u32>,
+    /// Instructions executed
+    pub total: u64,
+    /// Sorted by [`order`]
+    metrics: Vec<(Cow<'static, str>, u64)>,
+}
+
+/// Panics rather than wrapping in release builds, as a wrong count is worse than none
+pub(crate) fn checked_add(name: &str, lhs: u64, rhs: u64) -> u64 {
+    lhs.checked_add(rhs)
+        .unwrap_or_else(|| panic!("{name} overflowed adding {rhs} to {lhs}"))
 }
 
 impl std::ops::AddAssign for Statistics {
     fn add_assign(&mut self, rhs: Self) {
-        let Self {
-            total,
-            mem_read,
-            mem_write,
-            stack_read,
-            stack_write,
-            call,
-            branch,
-            r#return,
-            compare,
-            arithmetic,
-            logic,
-            others,
-        } = rhs;
-        self.total += total;
-        self.mem_read += mem_read;
-        self.mem_write += mem_write;
-        self.stack_read += stack_read;
-        self.stack_write += stack_write;
-        self.call += call;
-        self.branch += branch;
-        self.r#return += r#return;
-        self.compare += compare;
-        self.logic += logic;
-        self.arithmetic += arithmetic;
-        for (key, value) in others {
-            self.add_other(key, value);
+        self.add("total", rhs.total);
+        for (name, value) in rhs.metrics {
+            self.add(&name, value);
+        }
+    }
+}
+
+impl std::ops::SubAssign<&Statistics> for Statistics {
+    /// Only for removing counts which were added
+    fn sub_assign(&mut self, rhs: &Self) {
+        let sub = |name: &str, lhs: u64, rhs: u64| {
+            lhs.checked_sub(rhs)
+                .unwrap_or_else(|| panic!("{name} underflowed subtracting {rhs} from {lhs}"))
+        };
+        self.total = sub("total", self.total, rhs.total);
+        for (name, value) in &rhs.metrics {
+            if let Ok(idx) = self.position(name) {
+                self.metrics[idx].1 = sub(name, self.metrics[idx].1, *value);
+            }
         }
     }
 }
 
 impl Statistics {
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        let key = order(name);
+        self.metrics
+            .binary_search_by(|(existing, _)| order(existing).cmp(&key))
+    }
+
+    /// `total` then the metrics which have been added, in the order they are shown
+    pub fn metrics(&self) -> impl Iterator<Item = (&str, u64)> {
+        std::iter::once(("total", self.total)).chain(
+            self.metrics
+                .iter()
+                .map(|(name, value)| (name.as_ref(), *value)),
+        )
+    }
+
+    /// Whether the metric has been added (`total` always has)
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        name == "total" || self.position(name).is_ok()
+    }
+
+    /// Zero for metrics which have not been added
     #[must_use]
-    pub fn as_rows(&self) -> [(&str, u32); 12] {
-        let Self {
-            total,
-            mem_read,
-            mem_write,
-            stack_read,
-            stack_write,
-            call,
-            branch,
-            r#return,
-            compare,
-            arithmetic,
-            logic,
-            others,
-        } = self;
-        [
-            ("total", *total),
-            ("mem_read", *mem_read),
-            ("mem_write", *mem_write),
-            ("stack_read", *stack_read),
-            ("stack_write", *stack_write),
-            ("call", *call),
-            ("branch", *branch),
-            ("return", *r#return),
-            ("compare", *compare),
-            ("logic", *logic),
-            ("arithmetic", *arithmetic),
-            ("other", others.values().copied().sum()),
-        ]
-    }
-
-    pub fn add_other(&mut self, key: String, value: u32) {
-        // DO NOT DO THIS AS DOUBLE COUNTING self.total += value;
-        if let Some(existing) = self.others.get_mut(&key) {
-            *existing += value;
-        } else {
-            self.others.insert(key, value);
+    pub fn get(&self, name: &str) -> u64 {
+        if name == "total" {
+            return self.total;
+        }
+        self.position(name).map_or(0, |idx| self.metrics[idx].1)
+    }
+
+    pub fn set(&mut self, name: &str, value: u64) {
+        if name == "total" {
+            self.total = value;
+            return;
+        }
+        match self.position(name) {
+            Ok(idx) => self.metrics[idx].1 = value,
+            Err(idx) => self.metrics.insert(idx, (intern(name), value)),
+        }
+    }
+
+    /// Adds the metric if it is new. Does not add to `total`, as backends count an instruction in
+    /// `total` and in its kinds separately
+    ///
+    /// # Panics
+    /// If the count overflows
+    pub fn add(&mut self, name: &str, value: u64) {
+        if name == "total" {
+            self.total = checked_add(name, self.total, value);
+            return;
+        }
+        match self.position(name) {
+            Ok(idx) => self.metrics[idx].1 = checked_add(name, self.metrics[idx].1, value),
+            Err(idx) => self.metrics.insert(idx, (intern(name), value)),
+        }
+    }
+}
+
+/// Known metrics are not allocated
+fn intern(name: &str) -> Cow<'static, str> {
+    match METRICS.iter().find(|metric| **metric == name) {
+        Some(metric) => Cow::Borrowed(metric),
+        None => Cow::Owned(name.to_owned()),
+    }
+}
+
+/// Every metric in any of the statistics, in the order they are shown. The columns of a table
+pub fn metric_names<'a>(statistics: impl IntoIterator<Item = &'a Statistics>) -> Vec<String> {
+    let mut names: Vec<&str> = vec!["total"];
+    for statistics in statistics {
+        for (name, _) in &statistics.metrics {
+            let key = order(name);
+            if let Err(idx) = names[1..].binary_search_by(|existing| order(existing).cmp(&key)) {
+                names.insert(idx + 1, name);
+            }
         }
     }
+    names.into_iter().map(str::to_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Statistics;
+
+    fn statistics(total: u64, mem_read: u64) -> Statistics {
+        let mut statistics = Statistics::default();
+        statistics.add("total", total);
+        statistics.add("mem_read", mem_read);
+        statistics
+    }
+
+    #[test]
+    fn add_above_u32() {
+        let mut statistics = statistics(u64::from(u32::MAX), 1);
+        statistics += statistics.clone();
+        assert_eq!(statistics.total, 2 * u64::from(u32::MAX));
+        statistics.add("mem_read", u64::MAX - 2);
+        assert_eq!(statistics.get("mem_read"), u64::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "total overflowed")]
+    fn add_overflows() {
+        statistics(u64::MAX, 0).add("total", 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "mem_read overflowed")]
+    fn add_assign_overflows() {
+        let mut statistics = statistics(1, u64::MAX);
+        statistics += self::statistics(1, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "total underflowed")]
+    fn sub_assign_underflows() {
+        let mut statistics = statistics(1, 0);
+        statistics -= &self::statistics(2, 0);
+    }
+
+    #[test]
+    fn sub_assign_to_zero() {
+        let mut statistics = statistics(u64::MAX, u64::MAX);
+        statistics -= &statistics.clone();
+        assert_eq!(statistics.total, 0);
+        assert_eq!(statistics.get("mem_read"), 0);
+    }
 }
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
 use std::ffi::OsString;
 use std::io::Write;
 
+use depict::generics::Generic;
+use depict::grouping::{Group, GroupBy};
 use depict::{CommandRequest, Entry, Statistics, ToolOptions, ToolOutput, tools, utilities};
 use utilities::{Direction, Sorting};
 
@@ -13,89 +14,67 @@ fn main() {
     match tool {
         "--info" | "--help" | "help" => {
             println!("depict");
-            println!("run 'count', 'install'"); // , 'perf-events' or 'time'
-        }
-        "time" => {
-            todo!()
-        }
-        "qbdi" => {
-            let input = BenchmarkInput::from_arguments(args);
-
-            if input.limit != usize::MAX && input.sort.is_none() {
-                panic!("--limit requires --sort");
-            }
-            let request = CommandRequest {
-                program: input.program.into(),
-                arguments: input.arguments.into_iter().map(Into::into).collect(),
-            };
-            let options = ToolOptions {
-                keep: input.keep,
-                merge_internals: input.merge_internals,
-            };
-            let result = tools::qbdi::run_qbdi(request, &options).unwrap();
-            output_result(
-                result,
-                input.sort,
-                input.limit,
-                input.breakdown,
-                input.write_results_to,
+            println!(
+                "run 'count', 'time', 'perf-events', 'ptrace', 'annotate', 'compare', 'install'"
             );
         }
+        "time" | "qbdi" | "count" => run_benchmark(tool, BenchmarkInput::from_arguments(args)),
         #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
-        "sde" => {
-            let input = BenchmarkInput::from_arguments(args);
-
-            if input.limit != usize::MAX && input.sort.is_none() {
-                panic!("--limit requires --sort");
-            }
-            let request = CommandRequest {
-                program: input.program.into(),
-                arguments: input.arguments.into_iter().map(Into::into).collect(),
+        "sde" => run_benchmark(tool, BenchmarkInput::from_arguments(args)),
+        #[cfg(target_os = "linux")]
+        "perf-events" => run_benchmark(tool, BenchmarkInput::from_arguments(args)),
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        "ptrace" => run_benchmark(tool, BenchmarkInput::from_arguments(args)),
+        "annotate" => {
+            let Some(symbol) = args.next() else {
+                eprintln!("usage: depict annotate <symbol> <program> [arguments]");
+                std::process::exit(1);
             };
-            let options = ToolOptions {
-                keep: input.keep,
-                merge_internals: input.merge_internals,
-            };
-            let result = tools::sde::run_sde(request, &options).unwrap();
-            output_result(
-                result,
-                input.sort,
-                input.limit,
-                input.breakdown,
-                input.write_results_to,
-            );
+            annotate(&symbol, BenchmarkInput::from_arguments(args));
         }
-        "count" => {
-            let input = BenchmarkInput::from_arguments(args);
-
-            if input.limit != usize::MAX && input.sort.is_none() {
-                panic!("--limit requires --sort");
+        "compare" => {
+            let mut files = Vec::new();
+            let mut write_results_to = None;
+            let mut limit = usize::MAX;
+            let mut gate = RegressionGate::default();
+            while let Some(arg) = args.next() {
+                if gate.parse_argument(&arg, &mut args) {
+                    continue;
+                }
+                match arg.as_str() {
+                    "--write-results-to" => write_results_to = args.next(),
+                    "--limit" => limit = args.next().unwrap().parse().expect("invalid limit"),
+                    _ => files.push(arg),
+                }
             }
-            let request = CommandRequest {
-                program: input.program.into(),
-                arguments: input.arguments.into_iter().map(Into::into).collect(),
+            let [base, new] = files.as_slice() else {
+                eprintln!("usage: depict compare <base> <new>");
+                std::process::exit(1);
             };
-            let options = ToolOptions {
-                keep: input.keep,
-                merge_internals: input.merge_internals,
+            let (base, new) = match (read_results_file(base), read_results_file(new)) {
+                (Ok(base), Ok(new)) => (base, new),
+                (Err(error), _) | (_, Err(error)) => {
+                    eprintln!("{error}");
+                    std::process::exit(1);
+                }
             };
-            #[cfg(all(target_arch = "aarch64", target_os = "macos"))]
-            let result = tools::qbdi::run_qbdi(request, &options).unwrap();
-
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            let result = tools::sde::run_sde(request, &options).unwrap();
-
-            output_result(
-                result,
-                input.sort,
-                input.limit,
-                input.breakdown,
-                input.write_results_to,
-            );
-        }
-        #[cfg(target_family = "unix")]
-        "perf-events" => {
-            todo!()
+            let comparison = depict::compare::compare(&base, &new);
+            print_comparison(
+                &mut std::io::stdout(),
+                &comparison,
+                OutputFormat::default(),
+                limit,
+            )
+            .unwrap();
+            if let Some(path) = write_results_to {
+                let (mut file, format) = create_results_file(&path);
+                if let Err(error) = print_comparison(&mut file, &comparison, format, limit) {
+                    eprintln!("could not write {path}: {error}");
+                }
+            }
+            if gate.fail_above.is_some() || gate.thresholds.is_some() {
+                gate.check_comparison(&comparison);
+            }
         }
         "install" => {
             #[cfg(all(target_arch = "aarch64", target_os = "macos"))]
@@ -135,52 +114,478 @@ fn main() {
     }
 }
 
+fn run_tool(tool: &str, request: CommandRequest, options: &ToolOptions) -> Result<ToolOutput, ()> {
+    match tool {
+        "time" => tools::wall_clock::run(request, options),
+        "qbdi" => tools::qbdi::run_qbdi(request, options),
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
+        "sde" => tools::sde::run_sde(request, options),
+        "count" => {
+            #[cfg(all(target_arch = "aarch64", target_os = "macos"))]
+            let result = tools::qbdi::run_qbdi(request, options);
+
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            let result = if tools::sde::find_sde().is_some() {
+                tools::sde::run_sde(request, options)
+            } else {
+                eprintln!("'sde' not found, counting with ptrace (slow)");
+                tools::ptrace::run_ptrace(request, options)
+            };
+
+            #[cfg(all(
+                any(target_arch = "x86", target_arch = "x86_64"),
+                not(target_os = "linux")
+            ))]
+            let result = tools::sde::run_sde(request, options);
+
+            result
+        }
+        #[cfg(target_os = "linux")]
+        "perf-events" => tools::perf_events::run_perf_events(request, options),
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        "ptrace" => tools::ptrace::run_ptrace(request, options),
+        tool => unreachable!("{tool:?} is not a benchmarking tool"),
+    }
+}
+
+fn run_benchmark(tool: &str, input: BenchmarkInput) {
+    if input.limit != usize::MAX && input.sort.is_none() {
+        panic!("--limit requires --sort");
+    }
+
+    let options = input.tool_options();
+
+    if input.generic_arguments.is_empty() {
+        let request = CommandRequest {
+            program: input.program.into(),
+            arguments: input.arguments.into_iter().map(Into::into).collect(),
+        };
+        let Ok(mut result) = run_tool(tool, request, &options) else {
+            std::process::exit(1);
+        };
+        let mut generics = if input.merge_generics {
+            depict::generics::merge(&mut result)
+        } else {
+            Vec::new()
+        };
+        input.cost_model.apply(&mut result);
+        // Only listed when asked for, and for generics with more than one instantiation
+        generics.retain(|generic| input.instantiations > 0 && generic.instantiations.len() > 1);
+        for generic in &mut generics {
+            generic.instantiations.truncate(input.instantiations);
+        }
+        let gate = input.gate.is_active().then(|| {
+            let ToolOutput::SymbolInstructionCounts { symbols, total, .. } = &result else {
+                eprintln!("--baseline can only be used with instruction counts");
+                std::process::exit(1);
+            };
+            depict::compare::Results::from_output(symbols, total)
+        });
+        let exit = result.exit();
+        output_result(
+            result,
+            input.sort,
+            input.limit,
+            input.breakdown,
+            input.group_by,
+            &generics,
+            input.write_results_to,
+        );
+        // After the results are written, as they can help find why
+        if !exit.success() {
+            eprintln!("error: benchmark {exit}");
+            std::process::exit(1);
+        }
+        if let Some(results) = gate {
+            input.gate.check(&results);
+        }
+        return;
+    }
+
+    if input.gate.is_active() {
+        eprintln!("warning: --baseline is ignored for parameter sweeps");
+    }
+
+    let parameters = &input.generic_arguments;
+    let program = input.program.to_string_lossy();
+    let arguments: Vec<_> = input
+        .arguments
+        .iter()
+        .map(|argument| argument.to_string_lossy())
+        .collect();
+
+    for (name, _) in parameters {
+        let placeholder = format!("{{{name}}}");
+        let used = program.contains(&placeholder)
+            || arguments
+                .iter()
+                .any(|argument| argument.contains(&placeholder));
+        if !used {
+            eprintln!("warning: --arg {name:?} is not used by the program or its arguments");
+        }
+    }
+
+    let mut results = Vec::new();
+    for values in utilities::cartesian_product(parameters) {
+        let request = CommandRequest {
+            program: OsString::from(utilities::substitute_parameters(
+                &program, parameters, &values,
+            ))
+            .into(),
+            arguments: arguments
+                .iter()
+                .map(|argument| {
+                    OsString::from(utilities::substitute_parameters(
+                        argument, parameters, &values,
+                    ))
+                    .into()
+                })
+                .collect(),
+        };
+        let Ok(mut result) = run_tool(tool, request, &options) else {
+            std::process::exit(1);
+        };
+        if input.merge_generics {
+            depict::generics::merge(&mut result);
+        }
+        input.cost_model.apply(&mut result);
+        results.push((values, result));
+    }
+
+    let names: Vec<&str> = parameters.iter().map(|(name, _)| name.as_str()).collect();
+    print_sweep(
+        &mut std::io::stdout(),
+        &names,
+        &results,
+        OutputFormat::default(),
+        input.breakdown,
+    )
+    .unwrap();
+    if let Some(path) = input.write_results_to {
+        let (mut file, format) = create_results_file(&path);
+        if let Err(error) = print_sweep(&mut file, &names, &results, format, input.breakdown) {
+            eprintln!("could not write {path}: {error}");
+        }
+    }
+
+    let mut failed = false;
+    for (values, result) in &results {
+        let exit = result.exit();
+        if !exit.success() {
+            let values: Vec<String> = names
+                .iter()
+                .zip(values)
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            eprintln!("error: benchmark with {} {exit}", values.join(", "));
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Counts instructions (as `count` does) and prints the source of `symbol` with the instructions
+/// executed on each line
+fn annotate(symbol: &str, input: BenchmarkInput) {
+    let options = ToolOptions {
+        blocks: None,
+        ..input.tool_options()
+    };
+    let request = CommandRequest {
+        program: input.program.into(),
+        arguments: input.arguments.into_iter().map(Into::into).collect(),
+    };
+    let Ok(result) = run_tool("count", request, &options) else {
+        std::process::exit(1);
+    };
+    let exit = result.exit();
+    let ToolOutput::SymbolInstructionCounts { lines, .. } = result else {
+        unreachable!("count returns instruction counts")
+    };
+    if let Err(error) = print_annotation(&mut std::io::stdout(), symbol, &lines) {
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+    if !exit.success() {
+        eprintln!("error: benchmark {exit}");
+        std::process::exit(1);
+    }
+}
+
+fn read_results_file(path: &str) -> Result<depict::compare::Results, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| format!("could not read {path:?}: {error}"))?;
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str);
+    let result = match extension.map(OutputFormat::from_extension) {
+        Some(Ok(OutputFormat::JSON)) => depict::compare::parse_json_results(&content),
+        Some(Ok(OutputFormat::CSV)) => depict::compare::parse_csv_results(&content),
+        _ => Err("only JSON and CSV results files can be compared".to_owned()),
+    };
+    result.map_err(|error| format!("{path}: {error}"))
+}
+
+fn create_results_file(path: &str) -> (std::fs::File, OutputFormat) {
+    let path = std::path::Path::new(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    let file = std::fs::File::create(path).unwrap();
+    (file, OutputFormat::from_path(path))
+}
+
 fn output_result(
     result: ToolOutput,
     sort: Option<Sorting>,
     limit: usize,
     breakdown: bool,
+    group_by: Option<GroupBy>,
+    instantiations: &[Generic],
     write_results_to: Option<String>,
 ) {
+    write_output(
+        &mut std::io::stdout(),
+        &result,
+        OutputFormat::default(),
+        sort.clone(),
+        limit,
+        breakdown,
+        group_by,
+        instantiations,
+    )
+    .unwrap();
+    if let Some(path) = write_results_to {
+        let (mut file, format) = create_results_file(&path);
+        if let Err(error) = write_output(
+            &mut file,
+            &result,
+            format,
+            sort,
+            limit,
+            breakdown,
+            group_by,
+            instantiations,
+        ) {
+            eprintln!("could not write {path}: {error}");
+        }
+    }
+}
+
+/// `instantiations` are listed under the symbols of the whole program
+#[allow(clippy::too_many_arguments)]
+fn write_output(
+    to: &mut impl Write,
+    result: &ToolOutput,
+    format: OutputFormat,
+    sort: Option<Sorting>,
+    limit: usize,
+    breakdown: bool,
+    group_by: Option<GroupBy>,
+    instantiations: &[Generic],
+) -> std::io::Result<()> {
+    // Replaces the symbol table. Regions and threads are not grouped
+    if let (Some(by), ToolOutput::SymbolInstructionCounts { symbols, total, .. }) =
+        (group_by, result)
+        && matches!(
+            format,
+            OutputFormat::Plain | OutputFormat::JSON | OutputFormat::CSV | OutputFormat::Markdown
+        )
+    {
+        let groups = depict::grouping::group(symbols, by);
+        return print_groups(to, &groups, total, format, breakdown);
+    }
+
     match result {
-        ToolOutput::SymbolInstructionCounts { symbols, total } => {
-            // TODO cloning ...
+        ToolOutput::SymbolInstructionCounts {
+            symbols,
+            total,
+            calls,
+            ..
+        } if matches!(format, OutputFormat::Callgrind) => {
+            print_callgrind(to, symbols, total, calls)
+        }
+        ToolOutput::SymbolInstructionCounts { stacks, .. }
+            if matches!(format, OutputFormat::Folded) =>
+        {
+            if stacks.is_empty() {
+                return Err(std::io::Error::other(
+                    "this tool does not record call stacks",
+                ));
+            }
+            write!(to, "{}", depict::flamegraph::folded(stacks))
+        }
+        ToolOutput::SymbolInstructionCounts { stacks, .. }
+            if matches!(format, OutputFormat::Svg) =>
+        {
+            if stacks.is_empty() {
+                return Err(std::io::Error::other(
+                    "this tool does not record call stacks",
+                ));
+            }
+            write!(to, "{}", depict::flamegraph::svg(stacks, "Instructions"))
+        }
+        ToolOutput::SymbolInstructionCounts {
+            symbols,
+            stacks,
+            lines,
+            ..
+        } if matches!(format, OutputFormat::Pprof) => {
+            depict::pprof::write_profile(to, symbols, stacks, lines)
+        }
+        ToolOutput::SymbolInstructionCounts {
+            symbols,
+            total,
+            regions,
+            threads,
+            ..
+        } => {
+            // The same for regions and threads, as their CSV rows share the header
+            let metrics = breakdown.then(|| {
+                depict::metric_names(
+                    std::iter::once(total)
+                        .chain(symbols.iter().map(|entry| &entry.statistics))
+                        .chain(regions.iter().map(|region| &region.total))
+                        .chain(threads.iter().map(|thread| &thread.total)),
+                )
+            });
+            let breakdown = metrics.as_deref();
+            // Regions and threads follow the whole program. JSON key, heading and CSV prefix
+            let regions: Vec<(String, String, &Vec<Entry>, &Statistics)> = regions
+                .iter()
+                .map(|region| {
+                    let heading = match format {
+                        OutputFormat::Markdown => format!("Region `{}`", region.name),
+                        _ => format!("Region {}", region.name),
+                    };
+                    (region.name.clone(), heading, &region.symbols, &region.total)
+                })
+                .collect();
+            let threads: Vec<(String, String, &Vec<Entry>, &Statistics)> = threads
+                .iter()
+                .map(|thread| {
+                    let heading = format!("Thread {}", thread.index);
+                    (
+                        thread.index.to_string(),
+                        heading,
+                        &thread.symbols,
+                        &thread.total,
+                    )
+                })
+                .collect();
+
+            if regions.is_empty() && threads.is_empty() {
+                // TODO cloning ...
+                return print_results(
+                    to,
+                    symbols.clone(),
+                    total.clone(),
+                    format,
+                    sort,
+                    limit,
+                    breakdown,
+                    instantiations,
+                    None,
+                );
+            }
+
+            if let OutputFormat::JSON = format {
+                write!(to, "{{\"symbols\":")?;
+            }
             print_results(
-                &mut std::io::stdout(),
+                to,
                 symbols.clone(),
                 total.clone(),
-                OutputFormat::default(),
+                format,
                 sort.clone(),
                 limit,
                 breakdown,
-            )
-            .unwrap();
-            if let Some(path) = write_results_to {
-                let path = std::path::Path::new(&path);
-                if let Some(parent) = path.parent() {
-                    std::fs::create_dir_all(parent).unwrap();
-                }
-                let mut file = std::fs::File::create(path).unwrap();
-                let format =
-                    if let Some(extension) = path.extension().and_then(std::ffi::OsStr::to_str) {
-                        OutputFormat::from_extension(extension).unwrap_or_default()
+                instantiations,
+                None,
+            )?;
+            for (group, sections) in [("regions", regions), ("threads", threads)] {
+                if sections.is_empty() {
+                    continue;
+                }
+                if let OutputFormat::JSON = format {
+                    write!(to, ",\"{group}\":{{")?;
+                }
+                for (idx, (key, heading, symbols, total)) in sections.into_iter().enumerate() {
+                    match format {
+                        OutputFormat::Plain => writeln!(to, "\n{heading}")?,
+                        OutputFormat::Markdown => writeln!(to, "\n### {heading}\n")?,
+                        OutputFormat::JSON => {
+                            let separator = if idx == 0 { "" } else { "," };
+                            let key = key.replace('\\', "\\\\").replace('"', "\\\"");
+                            write!(to, "{separator}\"{key}\":")?;
+                        }
+                        OutputFormat::CSV
+                        | OutputFormat::Callgrind
+                        | OutputFormat::Folded
+                        | OutputFormat::Svg
+                        | OutputFormat::Pprof => {}
+                    }
+                    // Matches the prefix the comparison reads
+                    let section = if group == "threads" {
+                        format!("thread {key}")
                     } else {
-                        OutputFormat::default()
+                        key
                     };
-                print_results(&mut file, symbols, total, format, sort, limit, breakdown).unwrap();
+                    print_results(
+                        to,
+                        symbols.clone(),
+                        total.clone(),
+                        format,
+                        sort.clone(),
+                        limit,
+                        breakdown,
+                        &[],
+                        Some(&section),
+                    )?;
+                }
+                if let OutputFormat::JSON = format {
+                    write!(to, "}}")?;
+                }
             }
+            if let OutputFormat::JSON = format {
+                write!(to, "}}")?;
+            }
+            Ok(())
         }
-        _ => todo!(),
+        ToolOutput::Timings {
+            runs,
+            warmup,
+            statistics,
+            ..
+        } => print_timings(to, runs, *warmup, statistics, format),
+        #[cfg(target_os = "linux")]
+        ToolOutput::Counters { counters, .. } => print_counters(to, counters, format),
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
+        ToolOutput::HotBlocks { blocks, total, .. } => print_hot_blocks(to, blocks, *total, format),
+        // `ToolOutput` is non-exhaustive
+        _ => Err(std::io::Error::other(
+            "results of this tool cannot be written yet",
+        )),
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub enum OutputFormat {
     #[default]
     Plain,
     JSON,
     CSV,
     Markdown,
+    /// For KCachegrind/QCachegrind. Only for instruction counts
+    Callgrind,
+    /// Folded stacks. Only for instruction counts
+    Folded,
+    /// Flamegraph. Only for instruction counts
+    Svg,
+    /// Gzipped pprof profile. Only for instruction counts
+    Pprof,
 }
 
 impl OutputFormat {
@@ -190,9 +595,31 @@ impl OutputFormat {
             "json" => Ok(Self::JSON),
             "csv" => Ok(Self::CSV),
             "md" => Ok(Self::Markdown),
+            "callgrind" => Ok(Self::Callgrind),
+            "folded" => Ok(Self::Folded),
+            "svg" => Ok(Self::Svg),
+            "pprof" => Ok(Self::Pprof),
             unknown => Err(unknown),
         }
     }
+
+    /// Also handles `callgrind.out.*`, which is what KCachegrind looks for, and `.pb.gz`
+    pub fn from_path(path: &std::path::Path) -> Self {
+        let file_name = path.file_name().and_then(std::ffi::OsStr::to_str);
+        if file_name.is_some_and(|name| name.starts_with("callgrind.out")) {
+            Self::Callgrind
+        } else if file_name.is_some_and(|name| name.ends_with(".pb.gz")) {
+            Self::Pprof
+        } else if let Some(extension) = path.extension().and_then(std::ffi::OsStr::to_str) {
+            Self::from_extension(extension).unwrap_or_default()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+fn unsupported_format(format: OutputFormat, output: &str) -> std::io::Error {
+    std::io::Error::other(format!("{format:?} output is not supported for {output}"))
 }
 
 #[derive(Debug)]
@@ -206,21 +633,146 @@ pub struct BenchmarkInput {
     pub program: OsString,
     pub arguments: Vec<OsString>,
     // ...
-    pub generic_arguments: HashMap<String, Vec<String>>,
+    /// `--arg name=a,b,c`. `{name}` in the program arguments is substituted for each value
+    pub generic_arguments: Vec<(String, Vec<String>)>,
+    /// measured runs for `time`
+    pub runs: usize,
+    /// discarded runs before measuring for `time`
+    pub warmup: usize,
+    /// count kernel-mode events for `perf-events`
+    pub include_kernel: bool,
+    /// only count between `depict::start()` and `depict::stop()`
+    pub region_of_interest: bool,
+    /// attribute instructions to inlined functions
+    pub inline_frames: bool,
+    /// report the hottest basic blocks for `sde`
+    pub blocks: Option<usize>,
+    /// report each thread separately
+    pub per_thread: bool,
+    /// aggregate symbols by a prefix of their path
+    pub group_by: Option<GroupBy>,
+    /// sum the instantiations of generic functions
+    pub merge_generics: bool,
+    /// list the top instantiations under merged generic functions
+    pub instantiations: usize,
+    /// weights for `estimated_cycles`
+    pub cost_model: depict::cost::CostModel,
+    /// kill the program after this long
+    pub timeout: Option<std::time::Duration>,
 
     // TODO
     /// Save SDE file...
     pub keep: Option<String>,
-    /// skip Rust internals
-    pub merge_internals: bool,
+    /// `--include`, `--exclude` and `--merge-internals`
+    pub filter: depict::filter::Filter,
     /// include all instruction kinds
     pub breakdown: bool,
     // things
     pub write_results_to: Option<String>,
     pub write_to_stdout: bool,
+    pub gate: RegressionGate,
+}
+
+/// `--baseline`, `--fail-above` and `--thresholds`
+#[derive(Debug, Default)]
+pub struct RegressionGate {
+    /// results file to compare against
+    pub baseline: Option<String>,
+    /// for the total
+    pub fail_above: Option<depict::compare::Threshold>,
+    /// JSON file of per-symbol thresholds
+    pub thresholds: Option<String>,
+}
+
+impl RegressionGate {
+    fn parse_argument(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> bool {
+        match arg {
+            "--baseline" => self.baseline = args.next(),
+            "--fail-above" => {
+                let threshold = args.next().expect("expected threshold");
+                self.fail_above = Some(threshold.parse().unwrap_or_else(|error| {
+                    eprintln!("{error}");
+                    std::process::exit(1);
+                }));
+            }
+            "--thresholds" => self.thresholds = args.next(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn is_active(&self) -> bool {
+        self.baseline.is_some()
+    }
+
+    /// Compares `results` against the baseline and exits with a non-zero code if anything
+    /// regressed past its threshold
+    fn check(&self, results: &depict::compare::Results) {
+        let Some(ref baseline) = self.baseline else {
+            return;
+        };
+        let baseline = read_results_file(baseline).unwrap_or_else(|error| {
+            eprintln!("{error}");
+            std::process::exit(1);
+        });
+        self.check_comparison(&depict::compare::compare(&baseline, results));
+    }
+
+    fn check_comparison(&self, comparison: &depict::compare::Comparison) {
+        let overrides = match self.thresholds {
+            Some(ref path) => std::fs::read_to_string(path)
+                .map_err(|error| format!("could not read {path:?}: {error}"))
+                .and_then(|content| depict::compare::parse_thresholds(&content))
+                .unwrap_or_else(|error| {
+                    eprintln!("{error}");
+                    std::process::exit(1);
+                }),
+            None => Vec::new(),
+        };
+        if self.fail_above.is_none() && overrides.is_empty() {
+            eprintln!("warning: no --fail-above or --thresholds given, nothing to check");
+            return;
+        }
+
+        let regressions =
+            depict::compare::find_regressions(comparison, self.fail_above, &overrides);
+        if regressions.is_empty() {
+            eprintln!("no regressions past thresholds");
+            return;
+        }
+        for regression in &regressions {
+            let metric = regression.total;
+            eprintln!(
+                "regression: {name} {base} -> {new} ({percentage}) exceeds {threshold}",
+                name = regression.symbol_name,
+                base = metric.base,
+                new = metric.new,
+                percentage = metric
+                    .percentage()
+                    .map_or_else(|| "new".to_owned(), |p| format!("{p:+.2}%")),
+                threshold = regression.threshold,
+            );
+        }
+        std::process::exit(1);
+    }
 }
 
 impl BenchmarkInput {
+    fn tool_options(&self) -> ToolOptions {
+        ToolOptions {
+            keep: self.keep.clone(),
+            filter: self.filter.clone(),
+            iterations: self.runs,
+            warmup: self.warmup,
+            include_kernel: self.include_kernel,
+            region_of_interest: self.region_of_interest,
+            inline_frames: self.inline_frames,
+            per_thread: self.per_thread,
+            blocks: self.blocks,
+            timeout: self.timeout,
+        }
+    }
+
     pub fn from_arguments(mut args: impl Iterator<Item = String>) -> Self {
         let mut this = Self {
             limit: usize::MAX,
@@ -233,18 +785,34 @@ impl BenchmarkInput {
             program: OsString::new(),
             arguments: Vec::new(),
             // ...
-            generic_arguments: HashMap::new(),
+            generic_arguments: Vec::new(),
+            runs: 10,
+            warmup: 1,
+            include_kernel: false,
+            region_of_interest: false,
+            inline_frames: false,
+            blocks: None,
+            per_thread: false,
+            group_by: None,
+            merge_generics: false,
+            instantiations: 0,
+            cost_model: depict::cost::CostModel::default(),
+            timeout: None,
             // ...
             keep: None,
-            merge_internals: false,
+            filter: depict::filter::Filter::default(),
             breakdown: false,
             // ...
             write_results_to: None,
             write_to_stdout: true,
+            gate: RegressionGate::default(),
         };
 
         let mut left_over: Option<String> = None;
         while let Some(arg) = left_over.take().or_else(|| args.next()) {
+            if this.gate.parse_argument(&arg, &mut args) {
+                continue;
+            }
             match arg.as_str() {
                 // "--format" => {
                 //     let format = args.next().expect("no format given");
@@ -272,9 +840,9 @@ impl BenchmarkInput {
                     };
                     this.sort = Some(Sorting { field, direction });
                 }
-                // "--blocks" => {
-                //     blocks = args.next().unwrap().parse().expect("invalid top blocks");
-                // }
+                "--blocks" => {
+                    this.blocks = Some(args.next().unwrap().parse().expect("invalid top blocks"));
+                }
                 "--limit" => {
                     let limit = args.next().unwrap();
                     if "all" == limit {
@@ -283,6 +851,65 @@ impl BenchmarkInput {
                         this.limit = limit.parse().expect("invalid limit");
                     }
                 }
+                "--runs" => {
+                    this.runs = args.next().unwrap().parse().expect("invalid runs");
+                }
+                "--warmup" => {
+                    this.warmup = args.next().unwrap().parse().expect("invalid warmup");
+                }
+                "--kernel" => {
+                    this.include_kernel = true;
+                }
+                "--roi" => {
+                    this.region_of_interest = true;
+                }
+                "--per-thread" => {
+                    this.per_thread = true;
+                }
+                "--merge-generics" => {
+                    this.merge_generics = true;
+                }
+                "--instantiations" => {
+                    this.instantiations = args
+                        .next()
+                        .unwrap()
+                        .parse()
+                        .expect("invalid instantiations");
+                }
+                "--cost-model" => {
+                    let path = args.next().expect("expected cost model file");
+                    this.cost_model = std::fs::read_to_string(&path)
+                        .map_err(|error| format!("could not read {path:?}: {error}"))
+                        .and_then(|content| depict::cost::CostModel::from_json(&content))
+                        .unwrap_or_else(|error| {
+                            eprintln!("{path}: {error}");
+                            std::process::exit(1);
+                        });
+                }
+                "--timeout" => {
+                    let timeout = args.next().expect("expected timeout");
+                    this.timeout =
+                        Some(utilities::parse_duration(&timeout).unwrap_or_else(|error| {
+                            eprintln!("{error}");
+                            std::process::exit(1);
+                        }));
+                }
+                "--group-by" => {
+                    let by = args.next().expect("expected group");
+                    this.group_by = Some(by.parse().unwrap_or_else(|error| {
+                        eprintln!("{error}");
+                        std::process::exit(1);
+                    }));
+                }
+                "--inline-frames" => {
+                    this.inline_frames = match args.next().as_deref() {
+                        Some("on") => true,
+                        Some("off") => false,
+                        value => {
+                            panic!("expected 'on' or 'off' for --inline-frames, found {value:?}")
+                        }
+                    };
+                }
                 "--keep" => {
                     this.keep = args.next();
                 }
@@ -290,7 +917,13 @@ impl BenchmarkInput {
                     this.write_results_to = args.next();
                 }
                 "--merge-internals" => {
-                    this.merge_internals = true;
+                    this.filter.exclude("internal");
+                }
+                "--include" => {
+                    this.filter.include(&args.next().expect("expected pattern"));
+                }
+                "--exclude" => {
+                    this.filter.exclude(&args.next().expect("expected pattern"));
                 }
                 "--breakdown" => {
                     this.breakdown = true;
@@ -304,7 +937,13 @@ impl BenchmarkInput {
                     let (name, values) = next.split_once('=').unwrap();
                     // TODO CSV parse?
                     let values = values.trim().split(',').map(str::to_owned).collect();
-                    this.generic_arguments.insert(name.to_owned(), values);
+                    if let Some((_, existing)) =
+                        this.generic_arguments.iter_mut().find(|(n, _)| n == name)
+                    {
+                        *existing = values;
+                    } else {
+                        this.generic_arguments.push((name.to_owned(), values));
+                    }
                 }
                 // -- *program* *arg1* *arg2* ...
                 "--" => {
@@ -326,6 +965,10 @@ impl BenchmarkInput {
     }
 }
 
+/// `section` (region or thread) rows are written without a CSV header and prefixed with
+/// `[section] ` so they can follow the rows for the whole program. `breakdown` is the metrics to
+/// show, otherwise just `total`
+#[allow(clippy::too_many_arguments)]
 pub fn print_results(
     to: &mut impl Write,
     mut rows: Vec<Entry>,
@@ -333,7 +976,9 @@ pub fn print_results(
     output_format: OutputFormat,
     sorting: Option<utilities::Sorting>,
     limit: usize,
-    breakdown: bool,
+    breakdown: Option<&[String]>,
+    instantiations: &[Generic],
+    section: Option<&str>,
 ) -> std::io::Result<()> {
     use std::borrow::Cow;
     use utilities::count_with_seperator;
@@ -352,10 +997,19 @@ pub fn print_results(
                     sort.direction.compare(&lhs.symbol_name, &rhs.symbol_name)
                 });
             }
-            "total" => {
+            "inclusive" => {
+                let inclusive = |row: &Entry| row.inclusive.as_ref().map_or(0, |s| s.total);
+                rows.sort_unstable_by(|lhs, rhs| {
+                    sort.direction.compare(&inclusive(lhs), &inclusive(rhs))
+                });
+            }
+            field
+                if depict::METRICS.contains(&field)
+                    || rows.iter().any(|row| row.statistics.contains(field)) =>
+            {
                 rows.sort_unstable_by(|lhs, rhs| {
                     sort.direction
-                        .compare(&lhs.statistics.total, &rhs.statistics.total)
+                        .compare(&lhs.statistics.get(field), &rhs.statistics.get(field))
                 });
             }
             field => {
@@ -374,10 +1028,18 @@ pub fn print_results(
         0
     };
 
+    // Only when the tool tracks calls
+    let has_inclusive = rows.iter().any(|row| row.inclusive.is_some());
+    let instantiations: std::collections::HashMap<&str, &Generic> = instantiations
+        .iter()
+        .map(|generic| (generic.symbol_name.as_str(), generic))
+        .collect();
+
     rows.insert(
         0,
         Entry {
             symbol_name: "Total".into(),
+            inclusive: has_inclusive.then(|| total.clone()),
             statistics: total,
         },
     );
@@ -386,6 +1048,10 @@ pub fn print_results(
     let rows = &rows[..std::cmp::min(rows.len(), limit)];
 
     match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "symbol tables")),
         OutputFormat::Plain => {
             let max_name_width = {
                 let mut max_name_width = 0;
@@ -417,32 +1083,44 @@ pub fn print_results(
                 let fill = &WHITESPACE[..max_name_width - symbol_name.len()];
 
                 write!(to, "{symbol_name}{fill}")?;
-                if breakdown {
-                    let mut first = true;
-                    for (header, value) in row.statistics.as_rows() {
-                        let initial = if std::mem::take(&mut first) { "" } else { ", " };
+                if let Some(metrics) = breakdown {
+                    for (idx, name) in metrics.iter().enumerate() {
+                        let initial = if idx == 0 { "" } else { ", " };
                         write!(
                             to,
-                            "{initial}{header}: {value}",
-                            value = count_with_seperator(value as usize)
+                            "{initial}{name}: {value}",
+                            value = count_with_seperator(row.statistics.get(name))
                         )?;
                     }
-
-                    // TODO
-                    // for (name, count) in &row.statistics.others {
-                    //     write!(
-                    //         to,
-                    //         " {name}: {count}",
-                    //         count = count_with_seperator(*count as usize)
-                    //     )?;
-                    // }
-                    writeln!(to)?;
                 } else {
-                    writeln!(
+                    write!(
                         to,
                         "total: {count}",
-                        count = count_with_seperator(row.statistics.total as usize)
+                        count = count_with_seperator(row.statistics.total)
+                    )?;
+                }
+                if let Some(ref inclusive) = row.inclusive {
+                    write!(
+                        to,
+                        ", inclusive: {count}",
+                        count = count_with_seperator(inclusive.total)
+                    )?;
+                }
+                writeln!(to)?;
+                if let Some(generic) = instantiations.get(row.symbol_name.as_str()) {
+                    let plural = if generic.count == 1 { "" } else { "s" };
+                    writeln!(
+                        to,
+                        "    {count} instantiation{plural}",
+                        count = generic.count
                     )?;
+                    for (name, statistics) in &generic.instantiations {
+                        writeln!(
+                            to,
+                            "      {name}: {count}",
+                            count = count_with_seperator(statistics.total)
+                        )?;
+                    }
                 }
             }
 
@@ -454,17 +1132,41 @@ pub fn print_results(
                 if buf.len() > 1 {
                     buf.push(',');
                 }
-                if breakdown {
+                if breakdown.is_some() || instantiations.contains_key(row.symbol_name.as_str()) {
                     let mut builder = json_builder_macro::Builder::new(&mut buf);
                     builder.add("symbol_name", row.symbol_name.as_str());
-                    for (key, value) in row.statistics.as_rows() {
-                        if key == "other" {
-                            continue;
+                    if let Some(metrics) = breakdown {
+                        for name in metrics {
+                            builder.add(name, row.statistics.get(name));
+                        }
+                    } else {
+                        builder.add("total", row.statistics.total);
+                    }
+                    if let Some(ref inclusive) = row.inclusive {
+                        builder.add("inclusive", inclusive.total);
+                    }
+                    if let Some(generic) = instantiations.get(row.symbol_name.as_str()) {
+                        let mut list = String::from("[");
+                        for (name, statistics) in &generic.instantiations {
+                            if list.len() > 1 {
+                                list.push(',');
+                            }
+                            list.push_str(&json_builder_macro::json! {
+                                symbol_name: name.as_str(),
+                                total: statistics.total
+                            });
                         }
-                        builder.add(key, value);
+                        list.push(']');
+                        builder.add("instantiations", generic.count as u64);
+                        builder.add("top_instantiations", utilities::RawJSON(list));
                     }
-                    builder.add("other", row.statistics.others.clone());
                     builder.end();
+                } else if let Some(ref inclusive) = row.inclusive {
+                    buf.push_str(&json_builder_macro::json! {
+                        symbol_name: row.symbol_name.as_str(),
+                        total: row.statistics.total,
+                        inclusive: inclusive.total
+                    });
                 } else {
                     buf.push_str(&json_builder_macro::json! {
                         symbol_name: row.symbol_name.as_str(),
@@ -476,52 +1178,66 @@ pub fn print_results(
             write!(to, "{buf}")
         }
         OutputFormat::CSV => {
-            if breakdown {
-                write!(to, "symbol name")?;
-                for (header, _) in rows[0].statistics.as_rows() {
-                    write!(to, ",{header}")?;
+            if section.is_some() {
+                // Header already written
+            } else {
+                if let Some(metrics) = breakdown {
+                    write!(to, "symbol name")?;
+                    for name in metrics {
+                        write!(to, ",{name}")?;
+                    }
+                } else {
+                    write!(to, "symbol name,total")?;
+                }
+                if has_inclusive {
+                    write!(to, ",inclusive")?;
                 }
                 writeln!(to)?;
-            } else {
-                writeln!(to, "symbol name,total")?;
             }
             for row in rows {
                 let Entry {
                     symbol_name,
                     statistics,
+                    inclusive,
                 } = row;
-                if breakdown {
+                let symbol_name: Cow<'_, str> = match section {
+                    Some(section) => Cow::Owned(format!("[{section}] {symbol_name}")),
+                    None => Cow::Borrowed(symbol_name),
+                };
+                if let Some(metrics) = breakdown {
                     write!(to, "\"{symbol_name}\"")?;
-                    for (_, value) in statistics.as_rows() {
-                        write!(to, ",{value}")?;
+                    for name in metrics {
+                        write!(to, ",{value}", value = statistics.get(name))?;
                     }
-                    writeln!(to)?;
-                    // TODO ..?
-                    // for (name, count) in &row.others {
-                    //     write!(
-                    //         to,
-                    //         ",\"{name}\",{count}",
-                    //         count = *count as usize
-                    //     )?;
-                    // }
                 } else {
-                    writeln!(to, "\"{symbol_name}\",{total}", total = statistics.total)?;
+                    write!(to, "\"{symbol_name}\",{total}", total = statistics.total)?;
+                }
+                if let Some(inclusive) = inclusive {
+                    write!(to, ",{total}", total = inclusive.total)?;
+                } else if has_inclusive {
+                    write!(to, ",")?;
                 }
+                writeln!(to)?;
             }
             Ok(())
         }
         OutputFormat::Markdown => {
-            if breakdown {
-                let start = rows[0].statistics.as_rows();
+            if let Some(metrics) = breakdown {
                 write!(to, "|symbol name")?;
-                for (header, _) in &start {
-                    write!(to, "|{header}")?;
+                for name in metrics {
+                    write!(to, "|{name}")?;
+                }
+                if has_inclusive {
+                    write!(to, "|inclusive")?;
                 }
                 writeln!(to, "|")?;
-                for _ in 0..=start.len() {
+                for _ in 0..=(metrics.len() + usize::from(has_inclusive)) {
                     write!(to, "|---")?;
                 }
                 writeln!(to, "|")?;
+            } else if has_inclusive {
+                writeln!(to, "|symbol name|count|inclusive|")?;
+                writeln!(to, "|---|---|---|")?;
             } else {
                 writeln!(to, "|symbol name|count|")?;
                 writeln!(to, "|---|---|")?;
@@ -530,24 +1246,1026 @@ pub fn print_results(
                 let Entry {
                     symbol_name,
                     statistics,
+                    inclusive,
                 } = row;
-                if breakdown {
-                    write!(to, "\"{symbol_name}\"")?;
-                    for (_, value) in statistics.as_rows() {
-                        write!(to, ",{value}")?;
+                let inclusive = match inclusive {
+                    Some(inclusive) => Cow::Owned(format!("{}|", inclusive.total)),
+                    None if has_inclusive => Cow::Borrowed("|"),
+                    None => Cow::Borrowed(""),
+                };
+                if let Some(metrics) = breakdown {
+                    write!(to, "|`{symbol_name}`")?;
+                    for name in metrics {
+                        write!(to, "|{value}", value = statistics.get(name))?;
                     }
-                    writeln!(to)?;
-                    // TODO ..?
-                    // for (name, count) in &row.others {
-                    //     write!(
-                    //         to,
-                    //         "|\"{name}\"|{count}",
-                    //         count = *count as usize
-                    //     )?;
-                    // }
+                    writeln!(to, "|{inclusive}")?;
+                } else {
+                    writeln!(
+                        to,
+                        "|`{symbol_name}`|{total}|{inclusive}",
+                        total = statistics.total
+                    )?;
+                    if let Some(generic) = instantiations.get(symbol_name.as_str()) {
+                        let inclusive = if has_inclusive { "|" } else { "" };
+                        for (name, statistics) in &generic.instantiations {
+                            writeln!(
+                                to,
+                                "|↳ `{name}`|{total}|{inclusive}",
+                                total = statistics.total
+                            )?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `--group-by` results. An indented tree in plain output, nested objects in JSON and rows of
+/// `::` joined paths otherwise
+pub fn print_groups(
+    to: &mut impl Write,
+    groups: &[Group],
+    total: &Statistics,
+    output_format: OutputFormat,
+    breakdown: bool,
+) -> std::io::Result<()> {
+    use utilities::count_with_seperator;
+
+    /// Depth and path of every group, parents first
+    fn flatten<'a>(
+        groups: &'a [Group],
+        depth: usize,
+        parent: Option<&str>,
+        into: &mut Vec<(usize, String, &'a Group)>,
+    ) {
+        for group in groups {
+            let path = match parent {
+                Some(parent) => format!("{parent}::{name}", name = group.name),
+                None => group.name.clone(),
+            };
+            into.push((depth, path.clone(), group));
+            flatten(&group.children, depth + 1, Some(&path), into);
+        }
+    }
+
+    fn to_json(statistics: &Statistics, children: &[Group], metrics: Option<&[String]>) -> String {
+        let mut buf = String::new();
+        let mut builder = json_builder_macro::Builder::new(&mut buf);
+        if let Some(metrics) = metrics {
+            for name in metrics {
+                builder.add(name, statistics.get(name));
+            }
+        } else {
+            builder.add("total", statistics.total);
+        }
+        if !children.is_empty() {
+            let mut groups = String::new();
+            let mut inner = json_builder_macro::Builder::new(&mut groups);
+            for group in children {
+                inner.add(
+                    &group.name,
+                    utilities::RawJSON(to_json(&group.statistics, &group.children, metrics)),
+                );
+            }
+            inner.end();
+            builder.add("groups", utilities::RawJSON(groups));
+        }
+        builder.end();
+        buf
+    }
+
+    let mut rows = Vec::new();
+    flatten(groups, 0, None, &mut rows);
+    let metrics = breakdown.then(|| {
+        depict::metric_names(
+            std::iter::once(total).chain(rows.iter().map(|(_, _, group)| &group.statistics)),
+        )
+    });
+    let breakdown = metrics.as_deref();
+
+    match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "groups")),
+        OutputFormat::Plain => {
+            const MAX_WIDTH: usize = 100;
+            let name = |depth: usize, group: &Group| -> String {
+                let name = format!(
+                    "{indent}{name}",
+                    indent = "  ".repeat(depth),
+                    name = group.name
+                );
+                if name.chars().count() > MAX_WIDTH {
+                    let prefix: String = name.chars().take(MAX_WIDTH - 3).collect();
+                    format!("{prefix}...")
                 } else {
-                    writeln!(to, "|`{symbol_name}`|{total}|", total = statistics.total)?;
+                    name
                 }
+            };
+            let names: Vec<String> = rows
+                .iter()
+                .map(|(depth, _, group)| name(*depth, group))
+                .collect();
+            let width = names
+                .iter()
+                .map(|name| name.chars().count())
+                .chain(std::iter::once("Total".len()))
+                .max()
+                .unwrap_or_default();
+            let rows = std::iter::once(("Total".to_owned(), total)).chain(
+                names
+                    .into_iter()
+                    .zip(rows.iter().map(|(_, _, group)| &group.statistics)),
+            );
+            for (name, statistics) in rows {
+                let fill = " ".repeat(width - name.chars().count());
+                write!(to, "{name}{fill} ")?;
+                if let Some(metrics) = breakdown {
+                    for (idx, name) in metrics.iter().enumerate() {
+                        let initial = if idx == 0 { "" } else { ", " };
+                        write!(
+                            to,
+                            "{initial}{name}: {value}",
+                            value = count_with_seperator(statistics.get(name))
+                        )?;
+                    }
+                } else {
+                    write!(
+                        to,
+                        "total: {count}",
+                        count = count_with_seperator(statistics.total)
+                    )?;
+                }
+                writeln!(to)?;
+            }
+            Ok(())
+        }
+        OutputFormat::JSON => write!(to, "{}", to_json(total, groups, breakdown)),
+        OutputFormat::CSV => {
+            if let Some(metrics) = breakdown {
+                write!(to, "group")?;
+                for name in metrics {
+                    write!(to, ",{name}")?;
+                }
+                writeln!(to)?;
+            } else {
+                writeln!(to, "group,total")?;
+            }
+            let rows = std::iter::once(("Total", total)).chain(
+                rows.iter()
+                    .map(|(_, path, group)| (path.as_str(), &group.statistics)),
+            );
+            for (path, statistics) in rows {
+                if let Some(metrics) = breakdown {
+                    write!(to, "\"{path}\"")?;
+                    for name in metrics {
+                        write!(to, ",{value}", value = statistics.get(name))?;
+                    }
+                    writeln!(to)?;
+                } else {
+                    writeln!(to, "\"{path}\",{total}", total = statistics.total)?;
+                }
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => {
+            if let Some(metrics) = breakdown {
+                write!(to, "|group")?;
+                for name in metrics {
+                    write!(to, "|{name}")?;
+                }
+                writeln!(to, "|")?;
+                for _ in 0..=metrics.len() {
+                    write!(to, "|---")?;
+                }
+                writeln!(to, "|")?;
+            } else {
+                writeln!(to, "|group|count|")?;
+                writeln!(to, "|---|---|")?;
+            }
+            let rows = std::iter::once(("Total", total)).chain(
+                rows.iter()
+                    .map(|(_, path, group)| (path.as_str(), &group.statistics)),
+            );
+            for (path, statistics) in rows {
+                write!(to, "|`{path}`")?;
+                if let Some(metrics) = breakdown {
+                    for name in metrics {
+                        write!(to, "|{value}", value = statistics.get(name))?;
+                    }
+                } else {
+                    write!(to, "|{total}", total = statistics.total)?;
+                }
+                writeln!(to, "|")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// [Callgrind format](https://valgrind.org/docs/manual/cl-format.html). Counts are events
+/// (`total` as `Ir`) and attributed to line 0 of each function as there is no line information
+pub fn print_callgrind(
+    to: &mut impl Write,
+    symbols: &[Entry],
+    total: &Statistics,
+    calls: &[depict::Call],
+) -> std::io::Result<()> {
+    fn costs(statistics: &Statistics, events: &[String]) -> String {
+        let mut buf = String::from("0");
+        for name in events {
+            buf.push(' ');
+            buf.push_str(&statistics.get(name).to_string());
+        }
+        buf
+    }
+
+    let events = depict::metric_names(
+        std::iter::once(total)
+            .chain(symbols.iter().map(|entry| &entry.statistics))
+            .chain(calls.iter().map(|call| &call.inclusive)),
+    );
+
+    writeln!(to, "# callgrind format")?;
+    writeln!(to, "version: 1")?;
+    writeln!(to, "creator: depict")?;
+    writeln!(to, "positions: line")?;
+    write!(to, "events:")?;
+    for name in &events {
+        let name = if name == "total" { "Ir" } else { name };
+        write!(to, " {name}")?;
+    }
+    writeln!(to)?;
+    writeln!(to, "summary: {}", &costs(total, &events)[2..])?;
+
+    // Names are compressed. The first occurrence is `(id) name`, after that just `(id)`
+    let mut ids: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    let mut compressed = |name: &str| -> String {
+        if let Some(id) = ids.get(name) {
+            format!("({id})")
+        } else {
+            let id = ids.len() + 1;
+            ids.insert(name.to_owned(), id);
+            format!("({id}) {name}")
+        }
+    };
+
+    let mut calls_by_caller: std::collections::HashMap<&str, Vec<&depict::Call>> =
+        std::collections::HashMap::new();
+    // Skipping calls made while not counting (with `--roi`)
+    for call in calls.iter().filter(|call| call.inclusive.total != 0) {
+        calls_by_caller.entry(&call.caller).or_default().push(call);
+    }
+
+    for entry in symbols {
+        writeln!(to, "\nfn={}", compressed(&entry.symbol_name))?;
+        writeln!(to, "{}", costs(&entry.statistics, &events))?;
+        for call in calls_by_caller
+            .remove(entry.symbol_name.as_str())
+            .unwrap_or_default()
+        {
+            writeln!(to, "cfn={}", compressed(&call.callee))?;
+            writeln!(to, "calls={count} 0", count = call.count)?;
+            writeln!(to, "{}", costs(&call.inclusive, &events))?;
+        }
+    }
+    // Callers without counts of their own (for example filtered out)
+    for (caller, calls) in calls_by_caller {
+        writeln!(to, "\nfn={}", compressed(caller))?;
+        for call in calls {
+            writeln!(to, "cfn={}", compressed(&call.callee))?;
+            writeln!(to, "calls={count} 0", count = call.count)?;
+            writeln!(to, "{}", costs(&call.inclusive, &events))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Source of the files `symbol` has instructions in, with counts in the margin. `symbol` can be
+/// the full name or its last segments (`parse` or `Parser::parse` for `my_crate::Parser::parse`)
+pub fn print_annotation(
+    to: &mut impl Write,
+    symbol: &str,
+    lines: &[depict::Line],
+) -> Result<(), String> {
+    /// Lines without instructions shown around ones with them
+    const CONTEXT: u32 = 2;
+
+    if lines.is_empty() {
+        return Err("no line information. Is the program built with debug information?".to_owned());
+    }
+
+    let suffix = format!("::{symbol}");
+    let mut names: Vec<&str> = lines
+        .iter()
+        .map(|line| line.symbol_name.as_str())
+        .filter(|name| *name == symbol || name.ends_with(&suffix))
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    let name = match names.as_slice() {
+        [] => return Err(format!("no instructions attributed to lines of {symbol:?}")),
+        [name] => *name,
+        names if names.contains(&symbol) => symbol,
+        names => {
+            return Err(format!(
+                "{symbol:?} is ambiguous, could be any of {names}",
+                names = names.join(", ")
+            ));
+        }
+    };
+
+    let mut files: Vec<(&str, Vec<&depict::Line>)> = Vec::new();
+    for line in lines.iter().filter(|line| line.symbol_name == name) {
+        match files.iter_mut().find(|(file, _)| *file == line.file) {
+            Some((_, lines)) => lines.push(line),
+            None => files.push((&line.file, vec![line])),
+        }
+    }
+    let file_total =
+        |lines: &[&depict::Line]| -> u64 { lines.iter().map(|l| l.statistics.total).sum() };
+    files.sort_by_key(|(_, lines)| std::cmp::Reverse(file_total(lines)));
+
+    let total: u64 = files.iter().map(|(_, lines)| file_total(lines)).sum();
+    let io = |error: std::io::Error| error.to_string();
+    writeln!(to, "{name} ({total} instructions)").map_err(io)?;
+
+    for (file, lines) in files {
+        writeln!(to, "\n{file}").map_err(io)?;
+        let counts: std::collections::HashMap<u32, u64> = lines
+            .iter()
+            .map(|line| (line.line, line.statistics.total))
+            .collect();
+        let Ok(source) = std::fs::read_to_string(file) else {
+            // For example the standard library, which is not installed with its sources
+            writeln!(to, "(source not available)").map_err(io)?;
+            for line in lines {
+                writeln!(
+                    to,
+                    "{count:>12} | {line:>5}",
+                    count = line.statistics.total,
+                    line = line.line
+                )
+                .map_err(io)?;
+            }
+            continue;
+        };
+
+        let shown = |number: u32| {
+            (number.saturating_sub(CONTEXT)..=number + CONTEXT).any(|n| counts.contains_key(&n))
+        };
+        let mut skipped = false;
+        for (number, source) in (1..).zip(source.lines()) {
+            if !shown(number) {
+                skipped = true;
+                continue;
+            }
+            if std::mem::take(&mut skipped) {
+                writeln!(to, "{:>12} | {:>5} |", "", "...").map_err(io)?;
+            }
+            let count = counts.get(&number).map(u64::to_string).unwrap_or_default();
+            writeln!(to, "{count:>12} | {number:>5} | {source}").map_err(io)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn print_timings(
+    to: &mut impl Write,
+    runs: &[std::time::Duration],
+    warmup: usize,
+    statistics: &tools::wall_clock::TimingStatistics,
+    output_format: OutputFormat,
+) -> std::io::Result<()> {
+    match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "timings")),
+        OutputFormat::Plain => {
+            writeln!(to, "{count} runs ({warmup} warmup)", count = runs.len())?;
+            for (name, value) in statistics.as_rows() {
+                writeln!(to, "{name:<8}{value:.3?}")?;
+            }
+            if statistics.outliers.is_empty() {
+                writeln!(to, "no outliers")
+            } else {
+                write!(to, "{count} outliers:", count = statistics.outliers.len())?;
+                for idx in &statistics.outliers {
+                    write!(to, " run {idx} ({value:.3?})", value = runs[*idx])?;
+                }
+                writeln!(to)
+            }
+        }
+        OutputFormat::JSON => {
+            let mut buf = String::new();
+            let mut builder = json_builder_macro::Builder::new(&mut buf);
+            builder.add("warmup", warmup as u64);
+            for (name, value) in statistics.as_rows() {
+                builder.add(&format!("{name}_ns"), value.as_nanos() as u64);
+            }
+            let runs: Vec<u64> = runs.iter().map(|run| run.as_nanos() as u64).collect();
+            builder.add("runs_ns", runs);
+            let outliers: Vec<u64> = statistics.outliers.iter().map(|idx| *idx as u64).collect();
+            builder.add("outliers", outliers);
+            builder.end();
+            write!(to, "{buf}")
+        }
+        OutputFormat::CSV => {
+            writeln!(to, "run,nanoseconds,outlier")?;
+            for (idx, run) in runs.iter().enumerate() {
+                let outlier = statistics.outliers.contains(&idx);
+                writeln!(to, "{idx},{run},{outlier}", run = run.as_nanos())?;
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => {
+            writeln!(to, "|statistic|time|")?;
+            writeln!(to, "|---|---|")?;
+            for (name, value) in statistics.as_rows() {
+                writeln!(to, "|{name}|{value:.3?}|")?;
+            }
+            writeln!(to, "|runs|{count}|", count = runs.len())?;
+            writeln!(to, "|outliers|{count}|", count = statistics.outliers.len())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn print_counters(
+    to: &mut impl Write,
+    counters: &[tools::perf_events::Counter],
+    output_format: OutputFormat,
+) -> std::io::Result<()> {
+    use tools::perf_events::CounterValue;
+    use utilities::count_with_seperator;
+
+    match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "counters")),
+        OutputFormat::Plain => {
+            let max_name_width = counters.iter().map(|c| c.name.len()).max().unwrap_or(0);
+            for counter in counters {
+                let name = counter.name;
+                match &counter.value {
+                    CounterValue::Counted {
+                        value,
+                        running_fraction,
+                    } => {
+                        let value = count_with_seperator(*value);
+                        write!(to, "{name:<max_name_width$}  {value}")?;
+                        if *running_fraction < 1. {
+                            write!(to, " (scaled, ran {:.1}%)", running_fraction * 100.)?;
+                        }
+                        writeln!(to)?;
+                    }
+                    CounterValue::Unavailable(reason) => {
+                        writeln!(to, "{name:<max_name_width$}  <not available: {reason}>")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        OutputFormat::JSON => {
+            let mut buf = String::new();
+            let mut builder = json_builder_macro::Builder::new(&mut buf);
+            let mut unavailable = Vec::new();
+            for counter in counters {
+                match &counter.value {
+                    CounterValue::Counted { value, .. } => builder.add(counter.name, *value),
+                    CounterValue::Unavailable(_) => unavailable.push(counter.name),
+                }
+            }
+            builder.add("unavailable", unavailable);
+            builder.end();
+            write!(to, "{buf}")
+        }
+        OutputFormat::CSV => {
+            writeln!(to, "counter,value,running_fraction")?;
+            for counter in counters {
+                match &counter.value {
+                    CounterValue::Counted {
+                        value,
+                        running_fraction,
+                    } => writeln!(to, "{name},{value},{running_fraction}", name = counter.name)?,
+                    CounterValue::Unavailable(_) => writeln!(to, "{name},,", name = counter.name)?,
+                }
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => {
+            writeln!(to, "|counter|value|")?;
+            writeln!(to, "|---|---|")?;
+            for counter in counters {
+                match &counter.value {
+                    CounterValue::Counted { value, .. } => {
+                        writeln!(to, "|{name}|{value}|", name = counter.name)?;
+                    }
+                    CounterValue::Unavailable(_) => {
+                        writeln!(to, "|{name}|*not available*|", name = counter.name)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A table of the blocks, then the disassembly of each
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
+pub fn print_hot_blocks(
+    to: &mut impl Write,
+    blocks: &[tools::sde::Block],
+    total: u64,
+    output_format: OutputFormat,
+) -> std::io::Result<()> {
+    use utilities::count_with_seperator;
+
+    let percentage = |block: &tools::sde::Block| {
+        if total == 0 {
+            0.
+        } else {
+            block.instructions as f64 / total as f64 * 100.
+        }
+    };
+
+    match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "hot blocks")),
+        OutputFormat::Plain => {
+            let rows: Vec<[String; 6]> = blocks
+                .iter()
+                .enumerate()
+                .map(|(idx, block)| {
+                    [
+                        (idx + 1).to_string(),
+                        format!("{:x}", block.address),
+                        block.function.clone(),
+                        count_with_seperator(block.executions),
+                        count_with_seperator(block.instructions),
+                        format!("{:.2}%", percentage(block)),
+                    ]
+                })
+                .collect();
+            let headers = [
+                "block",
+                "address",
+                "function",
+                "executions",
+                "instructions",
+                "%",
+            ];
+            let widths: Vec<usize> = (0..headers.len())
+                .map(|column| {
+                    rows.iter()
+                        .map(|row| row[column].chars().count())
+                        .chain([headers[column].len()])
+                        .max()
+                        .unwrap_or_default()
+                })
+                .collect();
+            let header: Vec<String> = headers
+                .iter()
+                .zip(&widths)
+                .map(|(header, width)| format!("{header:<width$}"))
+                .collect();
+            writeln!(to, "{}", header.join("  ").trim_end())?;
+            for row in &rows {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .enumerate()
+                    // Numbers are right aligned
+                    .map(|(column, (value, width))| {
+                        if column >= 3 {
+                            format!("{value:>width$}")
+                        } else {
+                            format!("{value:<width$}")
+                        }
+                    })
+                    .collect();
+                writeln!(to, "{}", cells.join("  "))?;
+            }
+            for (idx, block) in blocks.iter().enumerate() {
+                writeln!(
+                    to,
+                    "\nBlock {idx} in {function}",
+                    idx = idx + 1,
+                    function = block.function
+                )?;
+                for (address, instruction) in &block.disassembly {
+                    writeln!(to, "    {address:x}  {instruction}")?;
+                }
+            }
+            Ok(())
+        }
+        OutputFormat::JSON => {
+            let mut buf = String::from("[");
+            for block in blocks {
+                if buf.len() > 1 {
+                    buf.push(',');
+                }
+                let disassembly: Vec<String> = block
+                    .disassembly
+                    .iter()
+                    .map(|(address, instruction)| format!("{address:x}: {instruction}"))
+                    .collect();
+                let mut builder = json_builder_macro::Builder::new(&mut buf);
+                builder.add("address", format!("{:x}", block.address));
+                builder.add("function", block.function.as_str());
+                builder.add("image", block.image.as_str());
+                builder.add("offset", format!("{:x}", block.offset));
+                builder.add("executions", block.executions);
+                builder.add("instructions", block.instructions);
+                builder.add("disassembly", disassembly);
+                builder.end();
+            }
+            buf.push(']');
+            write!(to, "{buf}")
+        }
+        OutputFormat::CSV => {
+            writeln!(to, "block,address,function,image,executions,instructions")?;
+            for (idx, block) in blocks.iter().enumerate() {
+                writeln!(
+                    to,
+                    "{idx},{address:x},{function},{image},{executions},{instructions}",
+                    idx = idx + 1,
+                    address = block.address,
+                    function = block.function,
+                    image = block.image,
+                    executions = block.executions,
+                    instructions = block.instructions,
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => {
+            writeln!(to, "|block|address|function|executions|instructions|%|")?;
+            writeln!(to, "|---|---|---|---|---|---|")?;
+            for (idx, block) in blocks.iter().enumerate() {
+                writeln!(
+                    to,
+                    "|{idx}|`{address:x}`|`{function}`|{executions}|{instructions}|{percentage:.2}%|",
+                    idx = idx + 1,
+                    address = block.address,
+                    function = block.function,
+                    executions = block.executions,
+                    instructions = block.instructions,
+                    percentage = percentage(block),
+                )?;
+            }
+            for (idx, block) in blocks.iter().enumerate() {
+                writeln!(
+                    to,
+                    "\n### Block {idx} in `{function}`\n",
+                    idx = idx + 1,
+                    function = block.function
+                )?;
+                writeln!(to, "```asm")?;
+                for (address, instruction) in &block.disassembly {
+                    writeln!(to, "{address:x}  {instruction}")?;
+                }
+                writeln!(to, "```")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Prints one row per combination of `--arg` values, with the parameters as the leading columns
+pub fn print_sweep(
+    to: &mut impl Write,
+    parameters: &[&str],
+    results: &[(Vec<&str>, ToolOutput)],
+    output_format: OutputFormat,
+    breakdown: bool,
+) -> std::io::Result<()> {
+    use std::borrow::Cow;
+    use utilities::count_with_seperator;
+
+    let summaries: Vec<_> = results
+        .iter()
+        .map(|(values, result)| (values, result.summary(breakdown)))
+        .collect();
+    if summaries.is_empty() {
+        return Ok(());
+    }
+    // Runs can have different metrics (with `--breakdown`), so rows are aligned to every header
+    let mut headers: Vec<Cow<'static, str>> = Vec::new();
+    for (_, summary) in &summaries {
+        for (name, _) in summary {
+            if !headers.contains(name) {
+                headers.push(name.clone());
+            }
+        }
+    }
+    let summaries: Vec<_> = summaries
+        .iter()
+        .map(|(values, summary)| {
+            let aligned: Vec<_> = headers
+                .iter()
+                .map(|header| {
+                    let value = summary
+                        .iter()
+                        .find_map(|(name, value)| (name == header).then_some(*value));
+                    (header.clone(), value.flatten())
+                })
+                .collect();
+            (values, aligned)
+        })
+        .collect();
+    let headers: Vec<&str> = headers.iter().map(AsRef::as_ref).collect();
+
+    match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "sweeps")),
+        OutputFormat::Plain => {
+            let cells: Vec<Vec<String>> = summaries
+                .iter()
+                .map(|(values, summary)| {
+                    values
+                        .iter()
+                        .map(|value| (*value).to_owned())
+                        .chain(summary.iter().map(|(_, value)| {
+                            value.map_or_else(|| "-".to_owned(), count_with_seperator)
+                        }))
+                        .collect()
+                })
+                .collect();
+            let widths: Vec<usize> = parameters
+                .iter()
+                .chain(headers.iter())
+                .enumerate()
+                .map(|(idx, header)| {
+                    cells
+                        .iter()
+                        .map(|row| row[idx].chars().count())
+                        .chain(std::iter::once(header.len()))
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+
+            let last = widths.len() - 1;
+            for (idx, header) in parameters.iter().chain(headers.iter()).enumerate() {
+                if idx == last {
+                    writeln!(to, "{header}")?;
+                } else {
+                    let width = widths[idx];
+                    write!(to, "{header:<width$}  ")?;
+                }
+            }
+            for row in &cells {
+                for (idx, cell) in row.iter().enumerate() {
+                    if idx == last {
+                        writeln!(to, "{cell}")?;
+                    } else {
+                        let fill = widths[idx] - cell.chars().count();
+                        write!(to, "{cell}{:fill$}  ", "")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        OutputFormat::JSON => {
+            let mut buf = String::from("[");
+            for (values, summary) in &summaries {
+                if buf.len() > 1 {
+                    buf.push(',');
+                }
+                let mut builder = json_builder_macro::Builder::new(&mut buf);
+                for (name, value) in parameters.iter().zip(values.iter()) {
+                    builder.add(name, *value);
+                }
+                for (name, value) in summary {
+                    if let Some(value) = value {
+                        builder.add(name, *value);
+                    }
+                }
+                builder.end();
+            }
+            buf.push(']');
+            write!(to, "{buf}")
+        }
+        OutputFormat::CSV => {
+            writeln!(
+                to,
+                "{}",
+                parameters
+                    .iter()
+                    .chain(headers.iter())
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )?;
+            for (values, summary) in &summaries {
+                let values = values.iter().map(|value| format!("\"{value}\""));
+                let summary = summary
+                    .iter()
+                    .map(|(_, value)| value.map(|v| v.to_string()).unwrap_or_default());
+                writeln!(
+                    to,
+                    "{}",
+                    values.chain(summary).collect::<Vec<_>>().join(",")
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => {
+            for header in parameters.iter().chain(headers.iter()) {
+                write!(to, "|{header}")?;
+            }
+            writeln!(to, "|")?;
+            for _ in 0..parameters.len() + headers.len() {
+                write!(to, "|---")?;
+            }
+            writeln!(to, "|")?;
+            for (values, summary) in &summaries {
+                for value in values.iter() {
+                    write!(to, "|`{value}`")?;
+                }
+                for (_, value) in summary {
+                    match value {
+                        Some(value) => write!(to, "|{value}")?,
+                        None => write!(to, "|-")?,
+                    }
+                }
+                writeln!(to, "|")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn print_comparison(
+    to: &mut impl Write,
+    comparison: &depict::compare::Comparison,
+    output_format: OutputFormat,
+    limit: usize,
+) -> std::io::Result<()> {
+    use depict::compare::{Change, MetricDiff, SymbolDiff};
+    use utilities::count_with_seperator;
+
+    fn status(diff: &SymbolDiff) -> &'static str {
+        match diff.change {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        }
+    }
+
+    fn signed_count(value: i64) -> String {
+        let sign = if value < 0 { "-" } else { "+" };
+        format!(
+            "{sign}{value}",
+            value = count_with_seperator(value.unsigned_abs())
+        )
+    }
+
+    fn percentage(metric: &MetricDiff) -> String {
+        metric
+            .percentage()
+            .map_or_else(|| "-".to_owned(), |percentage| format!("{percentage:+.2}%"))
+    }
+
+    let rows = std::iter::once(&comparison.total).chain(comparison.symbols.iter().take(limit));
+
+    match output_format {
+        format @ (OutputFormat::Callgrind
+        | OutputFormat::Folded
+        | OutputFormat::Svg
+        | OutputFormat::Pprof) => Err(unsupported_format(format, "comparisons")),
+        OutputFormat::Plain => {
+            let mut unchanged = 0;
+            for row in rows {
+                let changed = row.metrics.iter().any(|metric| metric.base != metric.new);
+                if !changed && !std::ptr::eq(row, &comparison.total) {
+                    unchanged += 1;
+                    continue;
+                }
+                write!(to, "{name}", name = row.symbol_name)?;
+                if row.change != Change::Changed {
+                    write!(to, " ({status})", status = status(row))?;
+                }
+                writeln!(to)?;
+                for metric in &row.metrics {
+                    if metric.base == metric.new && metric.name != "total" {
+                        continue;
+                    }
+                    writeln!(
+                        to,
+                        "    {name}: {base} -> {new} ({absolute}, {percentage})",
+                        name = metric.name,
+                        base = count_with_seperator(metric.base),
+                        new = count_with_seperator(metric.new),
+                        absolute = signed_count(metric.absolute()),
+                        percentage = percentage(metric),
+                    )?;
+                }
+            }
+            if unchanged > 0 {
+                writeln!(to, "{unchanged} symbols unchanged")?;
+            }
+            Ok(())
+        }
+        OutputFormat::JSON => {
+            let mut buf = String::from("[");
+            for row in rows {
+                if buf.len() > 1 {
+                    buf.push(',');
+                }
+                let mut builder = json_builder_macro::Builder::new(&mut buf);
+                builder.add("symbol_name", row.symbol_name.as_str());
+                builder.add("status", status(row));
+                for metric in &row.metrics {
+                    // NaN is not valid JSON
+                    let percentage = match metric.percentage() {
+                        Some(percentage) if percentage.is_finite() => percentage.to_string(),
+                        _ => "null".to_owned(),
+                    };
+                    let object = json_builder_macro::json! {
+                        base: metric.base,
+                        new: metric.new,
+                        change: metric.absolute(),
+                        percentage: utilities::RawJSON(percentage)
+                    };
+                    builder.add(&metric.name, utilities::RawJSON(object));
+                }
+                builder.end();
+            }
+            buf.push(']');
+            write!(to, "{buf}")
+        }
+        OutputFormat::CSV => {
+            write!(to, "symbol name,status")?;
+            for metric in &comparison.total.metrics {
+                let name = &metric.name;
+                write!(to, ",{name} base,{name} new,{name} change,{name} %")?;
+            }
+            writeln!(to)?;
+            for row in rows {
+                write!(
+                    to,
+                    "\"{name}\",{status}",
+                    name = row.symbol_name,
+                    status = status(row)
+                )?;
+                for metric in &row.metrics {
+                    let percentage = metric
+                        .percentage()
+                        .map(|percentage| format!("{percentage:.2}"))
+                        .unwrap_or_default();
+                    write!(
+                        to,
+                        ",{base},{new},{change},{percentage}",
+                        base = metric.base,
+                        new = metric.new,
+                        change = metric.absolute()
+                    )?;
+                }
+                writeln!(to)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => {
+            write!(to, "|symbol name|status")?;
+            for metric in &comparison.total.metrics {
+                write!(to, "|{name}|change", name = metric.name)?;
+            }
+            writeln!(to, "|")?;
+            for _ in 0..2 + comparison.total.metrics.len() * 2 {
+                write!(to, "|---")?;
+            }
+            writeln!(to, "|")?;
+            for row in rows {
+                write!(
+                    to,
+                    "|`{name}`|{status}",
+                    name = row.symbol_name,
+                    status = status(row)
+                )?;
+                for metric in &row.metrics {
+                    write!(
+                        to,
+                        "|{base} → {new}|{absolute} ({percentage})",
+                        base = metric.base,
+                        new = metric.new,
+                        absolute = signed_count(metric.absolute()),
+                        percentage = percentage(metric),
+                    )?;
+                }
+                writeln!(to, "|")?;
             }
             Ok(())
         }
@@ -0,0 +1,80 @@
+//! Marker functions for benchmarks. With `--roi`, only instructions executed between
+//! [`start`] and [`stop`] are counted. [`region`] reports the instructions executed in a closure
+//! separately. They do nothing otherwise.
+//!
+//! Each backend recognises them differently. ptrace and QBDI look for the (unmangled)
+//! `depict_start`, `depict_stop`, `depict_region_enter` and `depict_region_exit` symbols. On x86
+//! [`start`] and [`stop`] also emit SDE SSC marks
+
+/// SDE `-start_ssc_mark`
+pub const START_SSC_MARK: u32 = 0xDE9_0001;
+/// SDE `-stop_ssc_mark`
+pub const STOP_SSC_MARK: u32 = 0xDE9_0002;
+
+/// Start counting instructions (with `--roi`)
+#[inline(always)]
+pub fn start() {
+    depict_start();
+}
+
+/// Stop counting instructions (with `--roi`)
+#[inline(always)]
+pub fn stop() {
+    depict_stop();
+}
+
+/// Runs `f` in a named region. Instructions executed inside it (including in nested regions) are
+/// also reported in a separate section for the region. Nested regions are named by their path
+/// (`outer > inner`)
+#[inline(always)]
+pub fn region<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    depict_region_enter(name.as_ptr(), name.len());
+    let result = f();
+    depict_region_exit();
+    result
+}
+
+#[inline(never)]
+#[unsafe(no_mangle)]
+pub extern "C" fn depict_start() {
+    ssc_mark::<START_SSC_MARK>();
+}
+
+#[inline(never)]
+#[unsafe(no_mangle)]
+pub extern "C" fn depict_stop() {
+    ssc_mark::<STOP_SSC_MARK>();
+}
+
+/// `mov ebx, MARK` followed by the magic no-op SDE looks for. `rbx` cannot be an operand so it
+/// is saved and restored manually
+#[inline(always)]
+fn ssc_mark<const MARK: u32>() {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: only `rbx` is modified and it is restored
+    unsafe {
+        std::arch::asm!(
+            "push rbx",
+            "mov ebx, {mark}",
+            ".byte 0x64, 0x67, 0x90",
+            "pop rbx",
+            mark = const MARK,
+        );
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    std::hint::black_box(MARK);
+}
+
+/// Backends read the name from the arguments on entry
+#[inline(never)]
+#[unsafe(no_mangle)]
+pub extern "C" fn depict_region_enter(name: *const u8, length: usize) {
+    std::hint::black_box((name, length));
+}
+
+#[inline(never)]
+#[unsafe(no_mangle)]
+pub extern "C" fn depict_region_exit() {
+    std::hint::black_box(());
+}
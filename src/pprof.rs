@@ -0,0 +1,506 @@
+//! [pprof](https://github.com/google/pprof/blob/main/proto/profile.proto) `Profile` export. There
+//! is a sample type for each metric in [`Statistics`]. The protobuf is encoded by hand as only a few
+//! messages are needed
+
+use crate::{Entry, Line, Stack, Statistics};
+
+use std::collections::HashMap;
+
+/// Protobuf wire types
+const VARINT: u64 = 0;
+const LENGTH_DELIMITED: u64 = 2;
+
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint((field << 3) | wire_type);
+    }
+
+    fn uint(&mut self, field: u64, value: u64) {
+        // Zero is the default so can be skipped
+        if value != 0 {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+    }
+
+    fn bytes(&mut self, field: u64, value: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn message(&mut self, field: u64, message: &Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed(&mut self, field: u64, values: impl IntoIterator<Item = u64>) {
+        let mut packed = Message::default();
+        for value in values {
+            packed.varint(value);
+        }
+        self.bytes(field, &packed.0);
+    }
+}
+
+/// `string_table`. Index zero must be the empty string
+struct Strings<'a> {
+    indexes: HashMap<&'a str, u64>,
+    strings: Vec<&'a str>,
+}
+
+impl<'a> Strings<'a> {
+    fn new() -> Self {
+        Self {
+            indexes: HashMap::from([("", 0)]),
+            strings: vec![""],
+        }
+    }
+
+    fn index(&mut self, value: &'a str) -> u64 {
+        *self.indexes.entry(value).or_insert_with(|| {
+            self.strings.push(value);
+            self.strings.len() as u64 - 1
+        })
+    }
+}
+
+/// Instructions executed on each line of a function, for splitting samples of it
+struct FunctionLines<'a> {
+    /// With the most instructions, for the function itself
+    file: &'a str,
+    lines: Vec<(&'a str, u32, &'a Statistics)>,
+    /// Sum of `lines`
+    total: Statistics,
+}
+
+/// Functions are keyed by name and file, as lines from another file (inlined code) need their own
+#[derive(Default)]
+struct Ids<'a> {
+    functions: HashMap<(&'a str, &'a str), u64>,
+    /// Line zero is the function itself
+    locations: HashMap<(&'a str, &'a str, u32), u64>,
+}
+
+impl<'a> Ids<'a> {
+    /// Ids start at one
+    fn location(&mut self, name: &'a str, file: &'a str, line: u32) -> u64 {
+        let next = self.functions.len() as u64 + 1;
+        self.functions.entry((name, file)).or_insert(next);
+        let next = self.locations.len() as u64 + 1;
+        *self.locations.entry((name, file, line)).or_insert(next)
+    }
+}
+
+/// `value * part / whole`, rounded down
+fn proportion(value: u64, part: u64, whole: u64) -> u64 {
+    if whole == 0 {
+        0
+    } else {
+        (u128::from(value) * u128::from(part) / u128::from(whole)) as u64
+    }
+}
+
+/// Uncompressed `Profile`. Samples are the call stacks if there are any, otherwise one for each
+/// symbol (without callers). Samples are split across the `lines` of their innermost function, in
+/// proportion to the instructions executed on each
+#[must_use]
+pub fn profile(symbols: &[Entry], stacks: &[Stack], lines: &[Line]) -> Vec<u8> {
+    let mut strings = Strings::new();
+    let mut profile = Message::default();
+
+    let metrics = crate::metric_names(
+        symbols
+            .iter()
+            .map(|entry| &entry.statistics)
+            .chain(stacks.iter().map(|stack| &stack.statistics)),
+    );
+    let count = strings.index("count");
+    for name in &metrics {
+        let name = if name == "total" {
+            "instructions"
+        } else {
+            name
+        };
+        let mut value_type = Message::default();
+        value_type.uint(1, strings.index(name));
+        value_type.uint(2, count);
+        profile.message(1, &value_type);
+    }
+
+    let mut function_lines: HashMap<&str, FunctionLines> = HashMap::new();
+    for line in lines {
+        let function = function_lines
+            .entry(&line.symbol_name)
+            .or_insert_with(|| FunctionLines {
+                file: &line.file,
+                lines: Vec::new(),
+                total: Statistics::default(),
+            });
+        function
+            .lines
+            .push((&line.file, line.line, &line.statistics));
+        function.total += line.statistics.clone();
+    }
+    for function in function_lines.values_mut() {
+        let mut files: HashMap<&str, u64> = HashMap::new();
+        for (file, _, statistics) in &function.lines {
+            let total = files.entry(file).or_default();
+            *total = total.saturating_add(statistics.total);
+        }
+        if let Some((file, _)) = files
+            .into_iter()
+            .max_by_key(|(file, total)| (*total, std::cmp::Reverse(*file)))
+        {
+            function.file = file;
+        }
+    }
+    let file_of = |name: &str| {
+        function_lines
+            .get(name)
+            .map_or("", |function| function.file)
+    };
+
+    // Leaf first
+    let samples: Vec<(Vec<&str>, &Statistics)> = if stacks.is_empty() {
+        symbols
+            .iter()
+            .map(|entry| (vec![entry.symbol_name.as_str()], &entry.statistics))
+            .collect()
+    } else {
+        stacks
+            .iter()
+            .map(|stack| {
+                let frames = stack.frames.iter().rev().map(String::as_str).collect();
+                (frames, &stack.statistics)
+            })
+            .collect()
+    };
+
+    let mut ids = Ids::default();
+    let mut add_sample = |locations: Vec<u64>, values: &[u64]| {
+        let mut sample = Message::default();
+        sample.packed(1, locations);
+        sample.packed(2, values.iter().copied());
+        profile.message(2, &sample);
+    };
+    for (frames, statistics) in samples {
+        let Some((&leaf, callers)) = frames.split_first() else {
+            continue;
+        };
+        let callers: Vec<u64> = callers
+            .iter()
+            .map(|name| ids.location(name, file_of(name), 0))
+            .collect();
+        let values: Vec<u64> = metrics.iter().map(|name| statistics.get(name)).collect();
+        let Some(function) = function_lines.get(leaf) else {
+            let leaf = ids.location(leaf, file_of(leaf), 0);
+            add_sample(std::iter::once(leaf).chain(callers).collect(), &values);
+            continue;
+        };
+        let mut remaining = values.clone();
+        for (idx, (file, line, line_statistics)) in function.lines.iter().enumerate() {
+            // The last line gets what is left from rounding down
+            let split: Vec<u64> = if idx + 1 == function.lines.len() {
+                remaining.clone()
+            } else {
+                metrics
+                    .iter()
+                    .zip(&values)
+                    .map(|(name, value)| {
+                        proportion(*value, line_statistics.get(name), function.total.get(name))
+                    })
+                    .collect()
+            };
+            for (remaining, split) in remaining.iter_mut().zip(&split) {
+                *remaining -= split;
+            }
+            if split.iter().all(|value| *value == 0) {
+                continue;
+            }
+            let leaf = ids.location(leaf, file, *line);
+            add_sample(
+                std::iter::once(leaf)
+                    .chain(callers.iter().copied())
+                    .collect(),
+                &split,
+            );
+        }
+    }
+
+    let mut locations: Vec<((&str, &str, u32), u64)> = ids.locations.into_iter().collect();
+    locations.sort_unstable_by_key(|(_, id)| *id);
+
+    for ((name, file, line_number), id) in &locations {
+        let mut line = Message::default();
+        line.uint(1, ids.functions[&(*name, *file)]);
+        line.uint(2, u64::from(*line_number));
+        let mut location = Message::default();
+        location.uint(1, *id);
+        location.message(4, &line);
+        profile.message(4, &location);
+    }
+
+    let mut functions: Vec<((&str, &str), u64)> = ids.functions.into_iter().collect();
+    functions.sort_unstable_by_key(|(_, id)| *id);
+
+    for ((name, file), id) in &functions {
+        let name = strings.index(name);
+        let mut function = Message::default();
+        function.uint(1, *id);
+        function.uint(2, name);
+        function.uint(3, name);
+        function.uint(4, strings.index(file));
+        profile.message(5, &function);
+    }
+
+    // `period_type` and `period`. Every instruction is counted
+    let mut period_type = Message::default();
+    period_type.uint(1, strings.index("instructions"));
+    period_type.uint(2, count);
+
+    for string in &strings.strings {
+        profile.bytes(6, string.as_bytes());
+    }
+    profile.message(11, &period_type);
+    profile.uint(12, 1);
+    // `default_sample_type`
+    profile.uint(14, strings.index("instructions"));
+
+    profile.0
+}
+
+/// Gzipped `Profile`, as `pprof` expects in `.pb.gz` files
+///
+/// # Errors
+/// If writing fails
+pub fn write_profile(
+    to: &mut impl std::io::Write,
+    symbols: &[Entry],
+    stacks: &[Stack],
+    lines: &[Line],
+) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(to, flate2::Compression::default());
+    encoder.write_all(&profile(symbols, stacks, lines))?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_profile;
+    use crate::{Entry, Line, Stack, Statistics};
+
+    use std::collections::HashMap;
+
+    enum Field {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..).step_by(7) {
+            let (byte, rest) = bytes.split_first().unwrap();
+            *bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    fn decode(mut bytes: &[u8]) -> Vec<(u64, Field)> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes);
+            let field = match key & 7 {
+                0 => Field::Varint(varint(&mut bytes)),
+                2 => {
+                    let length = varint(&mut bytes) as usize;
+                    let (value, rest) = bytes.split_at(length);
+                    bytes = rest;
+                    Field::Bytes(value.to_vec())
+                }
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+            fields.push((key >> 3, field));
+        }
+        fields
+    }
+
+    fn messages(fields: &[(u64, Field)], field: u64) -> Vec<Vec<(u64, Field)>> {
+        fields
+            .iter()
+            .filter_map(|(number, value)| match value {
+                Field::Bytes(bytes) if *number == field => Some(decode(bytes)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn uint(fields: &[(u64, Field)], field: u64) -> u64 {
+        fields
+            .iter()
+            .find_map(|(number, value)| match value {
+                Field::Varint(value) if *number == field => Some(*value),
+                _ => None,
+            })
+            .unwrap_or(0)
+    }
+
+    fn packed(fields: &[(u64, Field)], field: u64) -> Vec<u64> {
+        let mut values = Vec::new();
+        for (number, value) in fields {
+            if let Field::Bytes(bytes) = value
+                && *number == field
+            {
+                let mut bytes = bytes.as_slice();
+                while !bytes.is_empty() {
+                    values.push(varint(&mut bytes));
+                }
+            }
+        }
+        values
+    }
+
+    type Sample<'a> = (Vec<(&'a str, u64)>, Vec<u64>);
+
+    fn statistics(total: u64, mem_read: u64) -> Statistics {
+        let mut statistics = Statistics {
+            total,
+            ..Statistics::default()
+        };
+        statistics.set("mem_read", mem_read);
+        statistics
+    }
+
+    #[test]
+    fn round_trip() {
+        let symbols = [
+            Entry {
+                symbol_name: "parse".to_owned(),
+                statistics: statistics(100, 10),
+                inclusive: None,
+            },
+            Entry {
+                symbol_name: "main".to_owned(),
+                statistics: statistics(20, 0),
+                inclusive: None,
+            },
+        ];
+        let stacks = [
+            Stack {
+                frames: vec!["main".to_owned(), "parse".to_owned()],
+                statistics: statistics(100, 10),
+            },
+            Stack {
+                frames: vec!["main".to_owned()],
+                statistics: statistics(20, 0),
+            },
+        ];
+        let lines = [3, 4].map(|line| Line {
+            symbol_name: "parse".to_owned(),
+            file: "src/parse.rs".to_owned(),
+            line,
+            statistics: if line == 3 {
+                statistics(60, 6)
+            } else {
+                statistics(40, 4)
+            },
+        });
+
+        let mut gzipped = Vec::new();
+        write_profile(&mut gzipped, &symbols, &stacks, &lines).unwrap();
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(
+            &mut flate2::read::GzDecoder::new(gzipped.as_slice()),
+            &mut bytes,
+        )
+        .unwrap();
+        let profile = decode(&bytes);
+
+        let strings: Vec<String> = profile
+            .iter()
+            .filter_map(|(number, value)| match value {
+                Field::Bytes(bytes) if *number == 6 => {
+                    Some(String::from_utf8(bytes.clone()).unwrap())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(strings[0], "");
+        let string = |index: u64| strings[index as usize].as_str();
+
+        let sample_types: Vec<(&str, &str)> = messages(&profile, 1)
+            .iter()
+            .map(|value_type| (string(uint(value_type, 1)), string(uint(value_type, 2))))
+            .collect();
+        assert_eq!(
+            sample_types,
+            [("instructions", "count"), ("mem_read", "count")]
+        );
+        assert_eq!(string(uint(&profile, 14)), "instructions");
+
+        // Id to name and file
+        let functions: HashMap<u64, (&str, &str)> = messages(&profile, 5)
+            .iter()
+            .map(|function| {
+                (
+                    uint(function, 1),
+                    (string(uint(function, 2)), string(uint(function, 4))),
+                )
+            })
+            .collect();
+        let mut names: Vec<_> = functions.values().copied().collect();
+        names.sort_unstable();
+        assert_eq!(names, [("main", ""), ("parse", "src/parse.rs")]);
+
+        // Id to function name and line
+        let locations: HashMap<u64, (&str, u64)> = messages(&profile, 4)
+            .iter()
+            .map(|location| {
+                let line = &messages(location, 4)[0];
+                (
+                    uint(location, 1),
+                    (functions[&uint(line, 1)].0, uint(line, 2)),
+                )
+            })
+            .collect();
+        assert_eq!(locations.len(), 3);
+
+        // Locations (leaf first) and values
+        let mut samples: Vec<Sample> = messages(&profile, 2)
+            .iter()
+            .map(|sample| {
+                let frames = packed(sample, 1)
+                    .into_iter()
+                    .map(|id| locations[&id])
+                    .collect();
+                (frames, packed(sample, 2))
+            })
+            .collect();
+        samples.sort_unstable();
+        assert_eq!(
+            samples,
+            [
+                (vec![("main", 0)], vec![20, 0]),
+                (vec![("parse", 3), ("main", 0)], vec![60, 6]),
+                (vec![("parse", 4), ("main", 0)], vec![40, 4]),
+            ]
+        );
+    }
+}
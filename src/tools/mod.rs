@@ -4,12 +4,126 @@ pub mod wall_clock;
 // #[cfg(target_os = "macos")]
 pub mod qbdi;
 
-#[cfg(target_family = "unix")]
+#[cfg(target_os = "linux")]
 pub mod perf_events;
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64", debug_assertions))]
 pub mod sde;
 
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod ptrace;
+
+/// Demangles a symbol name. Names the tool has already demangled as C++ (SDE) still have the
+/// escapes of Rust's legacy mangling (`$LT$`) and the hash, which are replaced and removed
+#[must_use]
+pub fn demangle(symbol_name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(symbol_name) {
+        return format!("{demangled:#}");
+    }
+    if !symbol_name.contains('$') && !symbol_name.contains("..") {
+        return symbol_name.to_owned();
+    }
+
+    let symbol_name = match symbol_name.rsplit_once("::h") {
+        Some((name, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            name
+        }
+        _ => symbol_name,
+    };
+    let mut demangled = String::with_capacity(symbol_name.len());
+    let mut rest = symbol_name;
+    while let Some(c) = rest.chars().next() {
+        // Identifiers starting with an escape are prefixed with `_`
+        let at_segment_start = demangled.is_empty() || demangled.ends_with("::");
+        if at_segment_start && rest.starts_with("_$") {
+            rest = &rest[1..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("..") {
+            demangled.push_str("::");
+            rest = after;
+            continue;
+        }
+        if c == '$'
+            && let Some((escape, after)) = rest[1..].split_once('$')
+        {
+            let replacement = match escape {
+                "SP" => Some('@'),
+                "BP" => Some('*'),
+                "RF" => Some('&'),
+                "LT" => Some('<'),
+                "GT" => Some('>'),
+                "LP" => Some('('),
+                "RP" => Some(')'),
+                "C" => Some(','),
+                escape => escape
+                    .strip_prefix('u')
+                    .and_then(|code| u32::from_str_radix(code, 16).ok())
+                    .and_then(char::from_u32),
+            };
+            if let Some(replacement) = replacement {
+                demangled.push(replacement);
+                rest = after;
+                continue;
+            }
+        }
+        demangled.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    demangled
+}
+
+/// Stacks from a calling context tree of parent context and (demangled) symbol name. Parents must
+/// come before their children
+pub(crate) fn context_stacks(
+    contexts: &[(Option<usize>, &str)],
+    weights: &[crate::Statistics],
+    filter: &crate::filter::Filter,
+) -> Vec<crate::Stack> {
+    let mut stacks = Vec::new();
+    for (context, weight) in weights.iter().enumerate() {
+        if weight.total == 0 {
+            continue;
+        }
+        let mut frames: Vec<&str> = Vec::new();
+        let mut at = Some(context);
+        while let Some(context) = at {
+            let (parent, name) = contexts[context];
+            let name = filter.name(name);
+            // Consecutive frames in the same bucket are one frame
+            if !(filter.is_bucket(name) && frames.last() == Some(&name)) {
+                frames.push(name);
+            }
+            at = parent;
+        }
+        stacks.push(crate::Stack {
+            frames: frames.into_iter().rev().map(str::to_owned).collect(),
+            statistics: weight.clone(),
+        });
+    }
+    stacks
+}
+
+/// Waits for the benchmarked program, killing it after `timeout`
+fn wait(
+    child: &mut std::process::Child,
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<crate::Exit> {
+    use wait_timeout::ChildExt;
+
+    let Some(timeout) = timeout else {
+        return child.wait().map(crate::Exit::from);
+    };
+    match child.wait_timeout(timeout)? {
+        Some(status) => Ok(crate::Exit::from(status)),
+        None => {
+            child.kill()?;
+            child.wait()?;
+            Ok(crate::Exit::TimedOut)
+        }
+    }
+}
+
 pub fn install_qbdi(lib: bool, qbdi: bool) {
     if qbdi {
         use std::process::{Command, Stdio};
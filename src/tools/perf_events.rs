@@ -1 +1,232 @@
-// TODO
+//! Hardware and software counters via `perf_event_open(2)`.
+//!
+//! Counters are opened on *this* process, disabled, with `inherit` and `enable_on_exec` set.
+//! The spawned child inherits them and they start counting when it `exec`s the benchmark, so
+//! nothing from `depict` itself (or the time between fork and exec) is counted. Inherited counts
+//! are folded back into our file descriptors when the child exits
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Command, Stdio};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_REFERENCES: u64 = 2;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+const PERF_COUNT_HW_BRANCH_INSTRUCTIONS: u64 = 4;
+const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+
+const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+const FLAG_DISABLED: u64 = 1 << 0;
+const FLAG_INHERIT: u64 = 1 << 1;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+const FLAG_ENABLE_ON_EXEC: u64 = 1 << 12;
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+/// `struct perf_event_attr` (`PERF_ATTR_SIZE_VER5`). The bitfield is represented as `flags`
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    r#type: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+/// Counters in the order they are reported
+const COUNTERS: &[(&str, u32, u64)] = &[
+    (
+        "instructions",
+        PERF_TYPE_HARDWARE,
+        PERF_COUNT_HW_INSTRUCTIONS,
+    ),
+    ("cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+    (
+        "branches",
+        PERF_TYPE_HARDWARE,
+        PERF_COUNT_HW_BRANCH_INSTRUCTIONS,
+    ),
+    (
+        "branch-misses",
+        PERF_TYPE_HARDWARE,
+        PERF_COUNT_HW_BRANCH_MISSES,
+    ),
+    (
+        "cache-references",
+        PERF_TYPE_HARDWARE,
+        PERF_COUNT_HW_CACHE_REFERENCES,
+    ),
+    (
+        "cache-misses",
+        PERF_TYPE_HARDWARE,
+        PERF_COUNT_HW_CACHE_MISSES,
+    ),
+    (
+        "task-clock-ns",
+        PERF_TYPE_SOFTWARE,
+        PERF_COUNT_SW_TASK_CLOCK,
+    ),
+    ("page-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS),
+    (
+        "context-switches",
+        PERF_TYPE_SOFTWARE,
+        PERF_COUNT_SW_CONTEXT_SWITCHES,
+    ),
+];
+
+#[derive(Clone, Debug)]
+pub struct Counter {
+    pub name: &'static str,
+    pub value: CounterValue,
+}
+
+#[derive(Clone, Debug)]
+pub enum CounterValue {
+    Counted {
+        /// Scaled by `running_fraction` if the counter was multiplexed
+        value: u64,
+        /// Fraction of the time the counter was enabled that it was actually on the PMU
+        running_fraction: f64,
+    },
+    /// Could not be opened on this machine (for example there is no PMU under virtualisation), or
+    /// was never scheduled
+    Unavailable(String),
+}
+
+fn open_counter(kind: u32, config: u64, include_kernel: bool) -> std::io::Result<OwnedFd> {
+    let mut flags = FLAG_DISABLED | FLAG_INHERIT | FLAG_ENABLE_ON_EXEC | FLAG_EXCLUDE_HV;
+    // Software events happen in the kernel (context switches are always attributed to it), so
+    // excluding it would make them zero
+    if !include_kernel && kind == PERF_TYPE_HARDWARE {
+        flags |= FLAG_EXCLUDE_KERNEL;
+    }
+    let attr = PerfEventAttr {
+        r#type: kind,
+        size: size_of::<PerfEventAttr>() as u32,
+        config,
+        read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+        flags,
+        ..PerfEventAttr::default()
+    };
+
+    // SAFETY: `attr` is a valid `perf_event_attr` which lives for the duration of the call
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &raw const attr,
+            0 as libc::pid_t,
+            -1 as libc::c_int,
+            -1 as libc::c_int,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        // SAFETY: the syscall succeeded and returned a new file descriptor that we own
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+    }
+}
+
+fn read_counter(fd: &OwnedFd) -> std::io::Result<CounterValue> {
+    // value, time_enabled, time_running
+    let mut buf = [0u64; 3];
+    // SAFETY: `buf` is large enough for the `read_format` requested in `open_counter`
+    let read = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), size_of_val(&buf)) };
+    if read < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let [value, enabled, running] = buf;
+    if running == 0 {
+        return Ok(CounterValue::Unavailable(
+            "never scheduled on the PMU".to_owned(),
+        ));
+    }
+    let (value, running_fraction) = if running < enabled {
+        let fraction = running as f64 / enabled as f64;
+        ((value as f64 / fraction) as u64, fraction)
+    } else {
+        (value, 1.)
+    };
+    Ok(CounterValue::Counted {
+        value,
+        running_fraction,
+    })
+}
+
+#[allow(clippy::result_unit_err)]
+pub fn run_perf_events(
+    request: crate::CommandRequest,
+    options: &crate::ToolOptions,
+) -> Result<crate::ToolOutput, ()> {
+    let opened: Vec<(&'static str, std::io::Result<OwnedFd>)> = COUNTERS
+        .iter()
+        .map(|(name, kind, config)| (*name, open_counter(*kind, *config, options.include_kernel)))
+        .collect();
+
+    if opened.iter().all(|(_, fd)| fd.is_err()) {
+        eprintln!(
+            "could not open any perf events. check /proc/sys/kernel/perf_event_paranoid ({error})",
+            error = opened[0].1.as_ref().err().unwrap()
+        );
+        return Err(());
+    }
+
+    let hardware_available = opened
+        .iter()
+        .zip(COUNTERS)
+        .any(|((_, fd), (_, kind, _))| *kind == PERF_TYPE_HARDWARE && fd.is_ok());
+    if !hardware_available {
+        eprintln!("no hardware counters available (no PMU?). only reporting software events");
+    }
+
+    let mut command = Command::new(request.program);
+    command.args(request.arguments);
+    command.stdout(Stdio::inherit());
+    command.stderr(Stdio::inherit());
+
+    let mut child = command.spawn().map_err(|error| {
+        eprintln!("could not run program: {error}");
+    })?;
+    let exit = super::wait(&mut child, options.timeout).map_err(|error| {
+        eprintln!("could not wait for program: {error}");
+    })?;
+
+    let counters = opened
+        .into_iter()
+        .map(|(name, fd)| {
+            let value = match fd.and_then(|fd| read_counter(&fd)) {
+                Ok(value) => value,
+                Err(error) => CounterValue::Unavailable(error.to_string()),
+            };
+            Counter { name, value }
+        })
+        .collect();
+
+    Ok(crate::ToolOutput::Counters { counters, exit })
+}
@@ -0,0 +1,1033 @@
+//! Counts instructions by single-stepping the program under `ptrace`. Slow but exact and needs
+//! nothing installed. Each instruction is attributed to a symbol by reading the ELF symbol tables
+//! of the files mapped into the process (found through `/proc/<pid>/maps`)
+
+use crate::elf::ElfFile;
+use crate::filter::Filter;
+use crate::{Entry, Statistics};
+
+use std::collections::HashMap;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+/// An executable region from `/proc/<pid>/maps`
+struct Mapping {
+    start: u64,
+    end: u64,
+    offset: u64,
+    path: String,
+}
+
+#[derive(Default)]
+struct Image {
+    /// `(file offset, virtual address, size)` of loadable segments
+    segments: Vec<(u64, u64, u64)>,
+    symbols: Vec<crate::elf::Symbol>,
+    /// Virtual address of the start of the image, for offsets from where it is loaded
+    base: u64,
+    /// Only loaded for `--inline-frames`
+    debug_info: Option<crate::debug_info::DebugInfo>,
+}
+
+impl Image {
+    fn load(path: &str, inline_frames: bool) -> Self {
+        let Ok(data) = std::fs::read(path) else {
+            return Self::default();
+        };
+        let Some(file) = ElfFile::parse(&data) else {
+            return Self::default();
+        };
+        Self {
+            segments: file
+                .segments
+                .iter()
+                .map(|segment| (segment.offset, segment.virtual_address, segment.file_size))
+                .collect(),
+            symbols: file.function_symbols(),
+            base: file
+                .segments
+                .iter()
+                .map(|segment| segment.virtual_address)
+                .min()
+                .unwrap_or_default()
+                & !0xfff,
+            debug_info: inline_frames
+                .then(|| crate::debug_info::DebugInfo::load(path))
+                .flatten(),
+        }
+    }
+
+    fn offset_to_virtual_address(&self, offset: u64) -> Option<u64> {
+        self.segments.iter().find_map(|(start, address, size)| {
+            (*start..start + size)
+                .contains(&offset)
+                .then(|| offset - start + address)
+        })
+    }
+}
+
+/// Resolves addresses in the traced process to symbol names
+#[derive(Default)]
+struct Symbolizer {
+    mappings: Vec<Mapping>,
+    images: HashMap<String, Image>,
+    /// Interned (demangled) names. Index is the symbol id
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    inline_frames: bool,
+}
+
+impl Symbolizer {
+    fn read_mappings(&mut self, pid: libc::pid_t) {
+        self.mappings.clear();
+        let Ok(maps) = std::fs::read_to_string(format!("/proc/{pid}/maps")) else {
+            return;
+        };
+        for line in maps.lines() {
+            // start-end perms offset dev inode path
+            let mut parts = line.split_ascii_whitespace();
+            let (Some(range), Some(permissions), Some(offset)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            if !permissions.contains('x') {
+                continue;
+            }
+            let path = parts.nth(2).unwrap_or_default().to_owned();
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end), Ok(offset)) = (
+                u64::from_str_radix(start, 16),
+                u64::from_str_radix(end, 16),
+                u64::from_str_radix(offset, 16),
+            ) else {
+                continue;
+            };
+            self.mappings.push(Mapping {
+                start,
+                end,
+                offset,
+                path,
+            });
+        }
+    }
+
+    fn intern(&mut self, name: String) -> usize {
+        if let Some(id) = self.ids.get(&name) {
+            *id
+        } else {
+            let id = self.names.len();
+            self.ids.insert(name.clone(), id);
+            self.names.push(name);
+            id
+        }
+    }
+
+    /// Image path and offset from where it is loaded, for [`crate::debug_info`]. Only uses mappings
+    /// and images already read
+    fn image_offset(&self, pc: u64) -> Option<(&str, u64)> {
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|m| (m.start..m.end).contains(&pc))?;
+        let image = self.images.get(&mapping.path)?;
+        let address = image.offset_to_virtual_address(pc - mapping.start + mapping.offset)?;
+        Some((&mapping.path, address - image.base))
+    }
+
+    /// Ids of the functions inlined at `pc`, outermost first. Empty without `--inline-frames`
+    fn inlined(&mut self, pc: u64) -> Vec<usize> {
+        let Some((path, offset)) = self.image_offset(pc) else {
+            return Vec::new();
+        };
+        let names = self.images[path]
+            .debug_info
+            .as_ref()
+            .map(|debug_info| debug_info.inlined_functions(offset))
+            .unwrap_or_default();
+        names.into_iter().map(|name| self.intern(name)).collect()
+    }
+
+    /// Moves counts by runtime address into `addresses`. Needed before the address space changes
+    fn flush_addresses(
+        &self,
+        counts: &mut HashMap<u64, (usize, Statistics)>,
+        addresses: &mut crate::debug_info::Addresses,
+    ) {
+        for (pc, (id, statistics)) in counts.drain() {
+            let Some((path, offset)) = self.image_offset(pc) else {
+                continue;
+            };
+            let entry = addresses
+                .entry((path.to_owned(), offset))
+                .or_insert_with(|| (self.names[id].clone(), Statistics::default()));
+            entry.1 += statistics;
+        }
+    }
+
+    /// Returns the symbol id and the runtime address range that resolves to the same symbol
+    fn resolve(&mut self, pid: libc::pid_t, pc: u64) -> (usize, std::ops::Range<u64>) {
+        let mut mapping = self
+            .mappings
+            .iter()
+            .position(|m| (m.start..m.end).contains(&pc));
+        if mapping.is_none() {
+            // Libraries may have been loaded since the last read
+            self.read_mappings(pid);
+            mapping = self
+                .mappings
+                .iter()
+                .position(|m| (m.start..m.end).contains(&pc));
+        }
+        let Some(mapping) = mapping else {
+            return (self.intern("[unknown]".to_owned()), pc..pc + 1);
+        };
+
+        let Mapping {
+            start,
+            end,
+            offset,
+            ref path,
+        } = self.mappings[mapping];
+        let path = path.clone();
+        let image = self
+            .images
+            .entry(path.clone())
+            .or_insert_with(|| Image::load(&path, self.inline_frames));
+
+        let symbol = image
+            .offset_to_virtual_address(pc - start + offset)
+            .and_then(|address| {
+                let idx = image
+                    .symbols
+                    .partition_point(|symbol| symbol.address <= address)
+                    .checked_sub(1)?;
+                let symbol = &image.symbols[idx];
+                let symbol_end = if symbol.size > 0 {
+                    symbol.address + symbol.size
+                } else {
+                    image
+                        .symbols
+                        .get(idx + 1)
+                        .map_or(u64::MAX, |next| next.address)
+                };
+                (address < symbol_end).then(|| {
+                    let bias = pc - address;
+                    let range = (symbol.address + bias).max(start)
+                        ..symbol_end.saturating_add(bias).min(end);
+                    (super::demangle(&symbol.name), range)
+                })
+            });
+
+        if let Some((name, range)) = symbol {
+            (self.intern(name), range)
+        } else {
+            let name = match path.rsplit_once('/') {
+                Some((_, file_name)) => format!("[{file_name}]"),
+                None if path.is_empty() => "[anonymous]".to_owned(),
+                None => path,
+            };
+            (self.intern(name), pc..pc + 1)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Call,
+    Return,
+    Branch,
+    Compare,
+    Logic,
+    Arithmetic,
+}
+
+/// Roughly classifies an x86-64 instruction from its first bytes. Only looks at the opcode (and
+/// the `reg` field of ModRM for the group opcodes)
+fn classify(bytes: [u8; 8]) -> Option<Kind> {
+    const LEGACY_PREFIXES: &[u8] = &[
+        0xF0, 0xF2, 0xF3, 0x2E, 0x36, 0x3E, 0x26, 0x64, 0x65, 0x66, 0x67,
+    ];
+    const REX: std::ops::RangeInclusive<u8> = 0x40..=0x4F;
+
+    let mut bytes = bytes
+        .into_iter()
+        .skip_while(|byte| LEGACY_PREFIXES.contains(byte) || REX.contains(byte));
+    let opcode = bytes.next()?;
+    let mut modrm_reg = || bytes.next().map(|modrm| (modrm >> 3) & 0b111);
+    match opcode {
+        0xE8 => Some(Kind::Call),
+        0xC2 | 0xC3 | 0xCA | 0xCB => Some(Kind::Return),
+        0x70..=0x7F | 0xE3 | 0xE9 | 0xEB => Some(Kind::Branch),
+        0x0F => matches!(bytes.next()?, 0x80..=0x8F).then_some(Kind::Branch),
+        0xFF => match modrm_reg()? {
+            2 | 3 => Some(Kind::Call),
+            4 | 5 => Some(Kind::Branch),
+            0 | 1 => Some(Kind::Arithmetic),
+            _ => None,
+        },
+        0x38..=0x3D | 0x84 | 0x85 | 0xA8 | 0xA9 => Some(Kind::Compare),
+        0x08..=0x0D | 0x20..=0x25 | 0x30..=0x35 => Some(Kind::Logic),
+        0x00..=0x05 | 0x10..=0x15 | 0x18..=0x1D | 0x28..=0x2D => Some(Kind::Arithmetic),
+        0x80..=0x83 => match modrm_reg()? {
+            7 => Some(Kind::Compare),
+            1 | 4 | 6 => Some(Kind::Logic),
+            _ => Some(Kind::Arithmetic),
+        },
+        0xF6 | 0xF7 => match modrm_reg()? {
+            0 | 1 => Some(Kind::Compare),
+            2 => Some(Kind::Logic),
+            _ => Some(Kind::Arithmetic),
+        },
+        _ => None,
+    }
+}
+
+fn wait(pid: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+    // SAFETY: `status` is a valid pointer
+    unsafe { libc::waitpid(pid, &raw mut status, libc::__WALL) };
+    status
+}
+
+fn add_instruction(statistics: &mut Statistics, kind: Option<Kind>) {
+    statistics.add("total", 1);
+    let name = match kind {
+        Some(Kind::Call) => "call",
+        Some(Kind::Return) => "return",
+        Some(Kind::Branch) => "branch",
+        Some(Kind::Compare) => "compare",
+        Some(Kind::Logic) => "logic",
+        Some(Kind::Arithmetic) => "arithmetic",
+        None => return,
+    };
+    statistics.add(name, 1);
+}
+
+/// Statistics indexed by symbol id
+#[derive(Default, Clone)]
+struct Counts {
+    exclusive: Vec<Statistics>,
+    /// Only filled in by [`CallStack`]
+    inclusive: Vec<Statistics>,
+    /// Number of calls and inclusive counts by caller and callee id. Only filled in by
+    /// [`CallStack`]
+    calls: HashMap<(usize, usize), (u64, Statistics)>,
+    /// Sum of `exclusive`
+    total: Statistics,
+}
+
+impl Counts {
+    fn record(&mut self, id: usize, kind: Option<Kind>) {
+        if self.exclusive.len() <= id {
+            self.exclusive.resize_with(id + 1, Statistics::default);
+        }
+        add_instruction(&mut self.exclusive[id], kind);
+        add_instruction(&mut self.total, kind);
+    }
+
+    /// Adds the counts of another thread
+    fn merge(&mut self, other: Counts) {
+        for (into, from) in [
+            (&mut self.exclusive, other.exclusive),
+            (&mut self.inclusive, other.inclusive),
+        ] {
+            if into.len() < from.len() {
+                into.resize_with(from.len(), Statistics::default);
+            }
+            for (into, from) in into.iter_mut().zip(from) {
+                *into += from;
+            }
+        }
+        for (edge, (count, inclusive)) in other.calls {
+            let call = self.calls.entry(edge).or_default();
+            call.0 = crate::checked_add("call count", call.0, count);
+            call.1 += inclusive;
+        }
+        self.total += other.total;
+    }
+
+    fn calls(&self, names: &[String], filter: &Filter) -> Vec<crate::Call> {
+        let name = |id: usize| -> &str { filter.name(&names[id]) };
+        let mut calls: HashMap<(&str, &str), (u64, Statistics)> = HashMap::new();
+        for ((caller, callee), (count, inclusive)) in &self.calls {
+            let call = calls.entry((name(*caller), name(*callee))).or_default();
+            call.0 = crate::checked_add("call count", call.0, *count);
+            call.1 += inclusive.clone();
+        }
+        calls
+            .into_iter()
+            .map(|((caller, callee), (count, inclusive))| crate::Call {
+                caller: caller.to_owned(),
+                callee: callee.to_owned(),
+                count,
+                inclusive,
+            })
+            .collect()
+    }
+
+    fn into_entries(self, names: &[String], filter: &Filter) -> (Vec<Entry>, Statistics) {
+        let mut buckets: Vec<(&str, Statistics)> = Vec::new();
+        let mut symbols = Vec::new();
+        let tracks_calls = !self.inclusive.is_empty();
+        let mut inclusive = self.inclusive.into_iter();
+        for (symbol_name, statistics) in names.iter().zip(self.exclusive) {
+            let inclusive = inclusive.next();
+            if statistics.total == 0 {
+                continue;
+            }
+            // Inlined functions are never on the call stack, and calls which were not followed
+            // (such as ones interrupted by `longjmp`) miss some instructions. Inclusive counts are
+            // at least the function's own counts
+            let inclusive = tracks_calls.then(|| {
+                let mut inclusive = inclusive.unwrap_or_default();
+                for (name, count) in statistics.metrics() {
+                    if inclusive.get(name) < count {
+                        inclusive.set(name, count);
+                    }
+                }
+                inclusive
+            });
+            if let Some(bucket) = filter.bucket(symbol_name) {
+                match buckets.iter_mut().find(|(name, _)| *name == bucket) {
+                    Some((_, into)) => *into += statistics,
+                    None => buckets.push((bucket, statistics)),
+                }
+            } else {
+                symbols.push(Entry {
+                    symbol_name: symbol_name.clone(),
+                    statistics,
+                    inclusive,
+                });
+            }
+        }
+        symbols.extend(buckets.into_iter().map(|(bucket, statistics)| Entry {
+            symbol_name: bucket.to_owned(),
+            statistics,
+            inclusive: None,
+        }));
+        (symbols, self.total)
+    }
+}
+
+/// Calls seen so far, for inclusive counts and stacks. Functions are pushed on the instruction
+/// after a call and popped on return. Jumps to the start of another function (PLT stubs and tail
+/// calls) replace the frame, as the function returns to the same caller
+#[derive(Default)]
+struct CallStack {
+    frames: Vec<Frame>,
+    /// Number of frames for each symbol id, so recursive calls are only counted once
+    depths: Vec<u32>,
+    /// Calling context tree. Parent context and symbol id
+    contexts: Vec<(Option<usize>, usize)>,
+    context_ids: HashMap<(Option<usize>, usize), usize>,
+    /// Instructions executed in each context
+    weights: Vec<Statistics>,
+}
+
+struct Frame {
+    id: usize,
+    caller: Option<usize>,
+    context: usize,
+    /// [`Counts::total`] when it was entered
+    entered_at: Statistics,
+}
+
+impl CallStack {
+    fn push(&mut self, id: usize, counts: &mut Counts) {
+        if self.depths.len() <= id {
+            self.depths.resize(id + 1, 0);
+        }
+        self.depths[id] += 1;
+        let caller = self.frames.last().map(|frame| frame.id);
+        if let Some(caller) = caller {
+            let call = counts.calls.entry((caller, id)).or_default();
+            call.0 = crate::checked_add("call count", call.0, 1);
+        }
+        let context = self.child_context(self.frames.last().map(|frame| frame.context), id);
+        self.frames.push(Frame {
+            id,
+            caller,
+            context,
+            entered_at: counts.total.clone(),
+        });
+    }
+
+    fn top(&self) -> Option<usize> {
+        self.frames.last().map(|frame| frame.id)
+    }
+
+    /// For when the function at the top jumped to the start of `id`
+    fn replace_top(&mut self, id: usize, counts: &mut Counts) {
+        self.pop(counts);
+        self.push(id, counts);
+    }
+
+    fn child_context(&mut self, parent: Option<usize>, id: usize) -> usize {
+        *self.context_ids.entry((parent, id)).or_insert_with(|| {
+            self.contexts.push((parent, id));
+            self.weights.push(Statistics::default());
+            self.contexts.len() - 1
+        })
+    }
+
+    /// Adds an instruction executed in `id` (and the functions inlined there, outermost first) to
+    /// the current stack
+    fn record(&mut self, id: usize, inlined: &[usize], kind: Option<Kind>) {
+        let top = self.frames.last().map(|frame| (frame.context, frame.id));
+        let mut context = match top {
+            Some((context, top_id)) if top_id == id => context,
+            // Entered by a jump
+            top => self.child_context(top.map(|(context, _)| context), id),
+        };
+        for inlined in inlined {
+            context = self.child_context(Some(context), *inlined);
+        }
+        add_instruction(&mut self.weights[context], kind);
+    }
+
+    fn stacks(&self, names: &[String], filter: &Filter) -> Vec<crate::Stack> {
+        let contexts: Vec<(Option<usize>, &str)> = self
+            .contexts
+            .iter()
+            .map(|(parent, id)| (*parent, names[*id].as_str()))
+            .collect();
+        super::context_stacks(&contexts, &self.weights, filter)
+    }
+
+    fn pop(&mut self, counts: &mut Counts) {
+        // Unbalanced (for example `longjmp`)
+        let Some(Frame {
+            id,
+            caller,
+            entered_at,
+            ..
+        }) = self.frames.pop()
+        else {
+            return;
+        };
+        let mut executed = counts.total.clone();
+        executed -= &entered_at;
+        if let Some(caller) = caller {
+            // Per call, so recursive calls are counted for each edge
+            counts.calls.entry((caller, id)).or_default().1 += executed.clone();
+        }
+        self.depths[id] -= 1;
+        if self.depths[id] == 0 {
+            if counts.inclusive.len() <= id {
+                counts.inclusive.resize_with(id + 1, Statistics::default);
+            }
+            counts.inclusive[id] += executed;
+        }
+    }
+
+    /// Pops everything, for when the program exits (or `exec`s)
+    fn unwind(&mut self, counts: &mut Counts) {
+        while !self.frames.is_empty() {
+            self.pop(counts);
+        }
+    }
+}
+
+/// Reads a (UTF-8) string from the tracee's memory
+fn read_string(pid: libc::pid_t, address: u64, length: u64) -> String {
+    const MAX_LENGTH: u64 = 256;
+
+    let length = length.min(MAX_LENGTH) as usize;
+    let mut bytes = Vec::with_capacity(length + 8);
+    let mut at = address;
+    while bytes.len() < length {
+        // SAFETY: `pid` is our stopped tracee
+        let word = unsafe { libc::ptrace(libc::PTRACE_PEEKDATA as _, pid, at, 0) };
+        bytes.extend_from_slice(&word.to_le_bytes());
+        at += 8;
+    }
+    bytes.truncate(length);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+enum StepStatus {
+    Trap,
+    Exec,
+    /// Created a thread
+    Clone,
+    /// Stopped for a signal which should be delivered when resuming
+    Signal(libc::c_int),
+    Exited,
+}
+
+fn step_status(status: libc::c_int) -> StepStatus {
+    if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+        StepStatus::Exited
+    } else if status >> 8 == libc::SIGTRAP | (libc::PTRACE_EVENT_EXEC << 8) {
+        StepStatus::Exec
+    } else if status >> 8 == libc::SIGTRAP | (libc::PTRACE_EVENT_CLONE << 8) {
+        StepStatus::Clone
+    } else if libc::WIFSTOPPED(status) && libc::WSTOPSIG(status) != libc::SIGTRAP {
+        StepStatus::Signal(libc::WSTOPSIG(status))
+    } else {
+        StepStatus::Trap
+    }
+}
+
+/// From the status of an exited thread
+fn exit(status: libc::c_int) -> crate::Exit {
+    if libc::WIFSIGNALED(status) {
+        crate::Exit::Signal(libc::WTERMSIG(status))
+    } else {
+        crate::Exit::Code(libc::WEXITSTATUS(status))
+    }
+}
+
+/// Waits for any thread. `None` when there are none left
+fn wait_any() -> Option<(libc::pid_t, libc::c_int)> {
+    let mut status = 0;
+    // SAFETY: `status` is a valid pointer
+    let tid = unsafe { libc::waitpid(-1, &raw mut status, libc::__WALL) };
+    (tid > 0).then_some((tid, status))
+}
+
+/// A traced thread. Each has its own counts so that inclusive counts (which are the difference in
+/// totals between a call and its return) do not include instructions run by other threads
+struct Thread {
+    tid: libc::pid_t,
+    /// In order of creation. The main thread is 0
+    index: usize,
+    counts: Counts,
+    call_stack: CallStack,
+    after_call: bool,
+    current: Option<(usize, std::ops::Range<u64>)>,
+    /// To deliver when resuming
+    signal: libc::c_int,
+    region_stack: Vec<usize>,
+    /// New threads start with a `SIGSTOP`, which should not be delivered
+    started: bool,
+}
+
+impl Thread {
+    fn new(tid: libc::pid_t, index: usize) -> Self {
+        Self {
+            tid,
+            index,
+            counts: Counts::default(),
+            call_stack: CallStack::default(),
+            // The first function is entered as if called
+            after_call: true,
+            current: None,
+            signal: 0,
+            region_stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    fn finish(mut self, finished: &mut Vec<Thread>) {
+        self.call_stack.unwind(&mut self.counts);
+        finished.push(self);
+    }
+}
+
+/// Kills the program after a timeout. On its own thread, as a tracee blocked in a system call
+/// (`sleep`) does not stop after a step until it returns
+struct Watchdog {
+    stop: std::sync::mpsc::Sender<()>,
+    handle: std::thread::JoinHandle<bool>,
+}
+
+impl Watchdog {
+    fn new(pid: libc::pid_t, timeout: std::time::Duration) -> Self {
+        // A pidfd cannot refer to another process if the program is reaped and its id reused.
+        // Before it is waited on, so it always refers to the program
+        // SAFETY: `pid` is our child, which has not been waited on
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } as libc::c_int;
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            let timed_out = matches!(
+                stopped.recv_timeout(timeout),
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+            );
+            // Every thread then exits, which ends tracing
+            // SAFETY: `pidfd` is open or `-1`, for kernels without pidfds
+            unsafe {
+                if timed_out {
+                    if pidfd == -1 {
+                        libc::kill(pid, libc::SIGKILL);
+                    } else {
+                        libc::syscall(
+                            libc::SYS_pidfd_send_signal,
+                            pidfd,
+                            libc::SIGKILL,
+                            std::ptr::null::<libc::siginfo_t>(),
+                            0,
+                        );
+                    }
+                }
+                if pidfd != -1 {
+                    libc::close(pidfd);
+                }
+            }
+            timed_out
+        });
+        Self { stop, handle }
+    }
+
+    /// Whether the program was killed
+    fn finish(self) -> bool {
+        drop(self.stop);
+        self.handle.join().unwrap()
+    }
+}
+
+#[allow(clippy::result_unit_err)]
+pub fn run_ptrace(
+    request: crate::CommandRequest,
+    options: &crate::ToolOptions,
+) -> Result<crate::ToolOutput, ()> {
+    let mut command = Command::new(request.program);
+    command.args(request.arguments);
+    command.stdout(Stdio::inherit());
+    command.stderr(Stdio::inherit());
+
+    // SAFETY: `ptrace` is async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            if libc::ptrace(libc::PTRACE_TRACEME as _, 0, 0, 0) == -1 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    }
+
+    let child = command.spawn().map_err(|error| {
+        eprintln!("could not run program: {error}");
+    })?;
+    let pid = child.id() as libc::pid_t;
+    let watchdog = options.timeout.map(|timeout| Watchdog::new(pid, timeout));
+
+    // Stopped at `exec`
+    let status = wait(pid);
+    if !libc::WIFSTOPPED(status) {
+        eprintln!("program did not start under ptrace");
+        return Err(());
+    }
+    // SAFETY: `pid` is our tracee
+    unsafe {
+        libc::ptrace(
+            libc::PTRACE_SETOPTIONS as _,
+            pid,
+            0,
+            libc::PTRACE_O_EXITKILL | libc::PTRACE_O_TRACEEXEC | libc::PTRACE_O_TRACECLONE,
+        );
+    }
+
+    let mut symbolizer = Symbolizer {
+        inline_frames: options.inline_frames,
+        ..Symbolizer::default()
+    };
+    let mut kinds: HashMap<u64, Option<Kind>> = HashMap::new();
+    // Functions inlined at each address, with `--inline-frames`
+    let mut inlined_at: HashMap<u64, Vec<usize>> = HashMap::new();
+    // Counts by runtime address, moved to `addresses` before `exec`
+    let mut pc_counts: HashMap<u64, (usize, Statistics)> = HashMap::new();
+    let mut addresses = crate::debug_info::Addresses::new();
+    // With `--roi`, wait for `depict_start`. For all threads
+    let mut counting = !options.region_of_interest;
+    let mut marker_seen = false;
+
+    let mut regions: Vec<(String, Counts)> = Vec::new();
+    let mut region_ids: HashMap<(Option<usize>, String), usize> = HashMap::new();
+
+    let mut threads = vec![Thread {
+        started: true,
+        ..Thread::new(pid, 0)
+    }];
+    let mut thread_count = 1;
+    let mut finished: Vec<Thread> = Vec::new();
+    // The thread which is stopped and about to execute an instruction
+    let mut tid = pid;
+    // Of the main thread, which is reported after all other threads have exited
+    let mut program_exit = crate::Exit::SUCCESS;
+
+    'trace: loop {
+        let thread = threads
+            .iter_mut()
+            .find(|thread| thread.tid == tid)
+            .expect("stopped thread is traced");
+
+        // SAFETY: `regs` is written by the kernel
+        let regs = unsafe {
+            let mut regs: libc::user_regs_struct = std::mem::zeroed();
+            libc::ptrace(libc::PTRACE_GETREGS as _, tid, 0, &raw mut regs);
+            regs
+        };
+        let pc = regs.rip;
+
+        let id = match thread.current {
+            Some((id, ref range)) if range.contains(&pc) => id,
+            _ => {
+                let previous = thread.current.as_ref().map(|(id, _)| *id);
+                let (id, range) = symbolizer.resolve(tid, pc);
+                if pc == range.start {
+                    // Not entered by a call, so a stub or tail call jumped here
+                    if !thread.after_call
+                        && previous.is_some_and(|previous| previous != id)
+                        && thread.call_stack.top() == previous
+                    {
+                        thread.call_stack.replace_top(id, &mut thread.counts);
+                    }
+                    match symbolizer.names[id].as_str() {
+                        "depict_start" => {
+                            counting = true;
+                            marker_seen = true;
+                        }
+                        "depict_stop" if options.region_of_interest => counting = false,
+                        "depict_region_enter" => {
+                            // `extern "C" fn(name: *const u8, length: usize)`
+                            let name = read_string(tid, regs.rdi, regs.rsi);
+                            let parent = thread.region_stack.last().copied();
+                            let region = *region_ids.entry((parent, name)).or_insert_with_key(
+                                |(parent, name)| {
+                                    let name = match parent {
+                                        Some(parent) => {
+                                            let parent = &regions[*parent].0;
+                                            format!(
+                                                "{parent}{separator}{name}",
+                                                separator = crate::REGION_SEPARATOR
+                                            )
+                                        }
+                                        None => name.clone(),
+                                    };
+                                    regions.push((name, Counts::default()));
+                                    regions.len() - 1
+                                },
+                            );
+                            thread.region_stack.push(region);
+                        }
+                        "depict_region_exit" => {
+                            thread.region_stack.pop();
+                        }
+                        _ => {}
+                    }
+                }
+                thread.current = Some((id, range));
+                id
+            }
+        };
+
+        let kind = *kinds.entry(pc).or_insert_with(|| {
+            // SAFETY: `tid` is our stopped tracee
+            let word = unsafe { libc::ptrace(libc::PTRACE_PEEKDATA as _, tid, pc, 0) };
+            classify(word.to_le_bytes())
+        });
+
+        if std::mem::take(&mut thread.after_call) {
+            thread.call_stack.push(id, &mut thread.counts);
+        }
+
+        let is_marker = matches!(
+            symbolizer.names[id].as_str(),
+            "depict_start" | "depict_stop" | "depict_region_enter" | "depict_region_exit"
+        );
+        if counting && !is_marker {
+            let inlined: &[usize] = if options.inline_frames {
+                inlined_at
+                    .entry(pc)
+                    .or_insert_with(|| symbolizer.inlined(pc))
+            } else {
+                &[]
+            };
+            // Attributed to the innermost inlined function
+            let leaf = inlined.last().copied().unwrap_or(id);
+            thread.counts.record(leaf, kind);
+            thread.call_stack.record(id, inlined, kind);
+            add_instruction(
+                &mut pc_counts
+                    .entry(pc)
+                    .or_insert((leaf, Statistics::default()))
+                    .1,
+                kind,
+            );
+            for region in &thread.region_stack {
+                regions[*region].1.record(leaf, kind);
+            }
+        }
+
+        match kind {
+            Some(Kind::Call) => thread.after_call = true,
+            Some(Kind::Return) => thread.call_stack.pop(&mut thread.counts),
+            _ => {}
+        }
+
+        // SAFETY: `tid` is our stopped tracee
+        unsafe { libc::ptrace(libc::PTRACE_SINGLESTEP as _, tid, 0, thread.signal) };
+        thread.signal = 0;
+
+        // Threads run concurrently, so the next stop can be any of them
+        tid = loop {
+            let Some((stopped, status)) = wait_any() else {
+                break 'trace;
+            };
+            let position = threads.iter().position(|thread| thread.tid == stopped);
+            let thread = match position {
+                Some(position) => &mut threads[position],
+                // A new thread, stopped before its creation was reported
+                None => {
+                    threads.push(Thread::new(stopped, thread_count));
+                    thread_count += 1;
+                    threads.last_mut().unwrap()
+                }
+            };
+            match step_status(status) {
+                StepStatus::Exited => {
+                    if stopped == pid {
+                        program_exit = exit(status);
+                    }
+                    let position = threads
+                        .iter()
+                        .position(|thread| thread.tid == stopped)
+                        .unwrap();
+                    threads.swap_remove(position).finish(&mut finished);
+                    if threads.is_empty() {
+                        break 'trace;
+                    }
+                }
+                StepStatus::Exec => {
+                    // New program image, so everything we know about the address space is stale.
+                    // Other threads are gone
+                    symbolizer.flush_addresses(&mut pc_counts, &mut addresses);
+                    symbolizer.mappings.clear();
+                    symbolizer.images.clear();
+                    kinds.clear();
+                    inlined_at.clear();
+                    for thread in std::mem::take(&mut threads) {
+                        thread.finish(&mut finished);
+                    }
+                    threads.push(Thread {
+                        started: true,
+                        ..Thread::new(stopped, thread_count)
+                    });
+                    thread_count += 1;
+                    break stopped;
+                }
+                StepStatus::Clone => {
+                    let mut new = 0 as libc::c_ulong;
+                    // SAFETY: `stopped` is our tracee and `new` is a valid pointer
+                    unsafe {
+                        libc::ptrace(libc::PTRACE_GETEVENTMSG as _, stopped, 0, &raw mut new);
+                    }
+                    let new = new as libc::pid_t;
+                    if !threads.iter().any(|thread| thread.tid == new) {
+                        threads.push(Thread::new(new, thread_count));
+                        thread_count += 1;
+                    }
+                    break stopped;
+                }
+                StepStatus::Signal(libc::SIGSTOP) if !thread.started => {
+                    thread.started = true;
+                    break stopped;
+                }
+                StepStatus::Signal(stop_signal) => {
+                    thread.signal = stop_signal;
+                    break stopped;
+                }
+                StepStatus::Trap => break stopped,
+            }
+        };
+    }
+
+    for thread in threads {
+        thread.finish(&mut finished);
+    }
+    let timed_out = watchdog.is_some_and(Watchdog::finish);
+    symbolizer.flush_addresses(&mut pc_counts, &mut addresses);
+
+    if options.region_of_interest && !marker_seen {
+        eprintln!("warning: `depict::start()` was never called, so nothing was counted");
+    }
+
+    finished.sort_by_key(|thread| thread.index);
+    let mut counts = Counts::default();
+    let mut stacks = Vec::new();
+    let mut per_thread = Vec::new();
+    for thread in finished {
+        stacks.extend(thread.call_stack.stacks(&symbolizer.names, &options.filter));
+        if options.per_thread {
+            let (symbols, total) = thread
+                .counts
+                .clone()
+                .into_entries(&symbolizer.names, &options.filter);
+            per_thread.push(crate::Thread {
+                index: thread.index,
+                symbols,
+                total,
+            });
+        }
+        counts.merge(thread.counts);
+    }
+
+    let calls = counts.calls(&symbolizer.names, &options.filter);
+    let (symbols, total) = counts.into_entries(&symbolizer.names, &options.filter);
+    let regions = regions
+        .into_iter()
+        .map(|(name, counts)| {
+            let (symbols, total) = counts.into_entries(&symbolizer.names, &options.filter);
+            crate::Region {
+                name,
+                symbols,
+                total,
+            }
+        })
+        .collect();
+
+    Ok(crate::ToolOutput::SymbolInstructionCounts {
+        symbols,
+        total,
+        regions,
+        calls,
+        stacks,
+        lines: crate::debug_info::lines(addresses),
+        threads: per_thread,
+        exit: if timed_out {
+            crate::Exit::TimedOut
+        } else {
+            program_exit
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CommandRequest, Exit, ToolOptions};
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn timeout_kills_a_blocked_program() {
+        let request = CommandRequest {
+            program: std::ffi::OsStr::new("/bin/sleep").into(),
+            arguments: vec![std::ffi::OsStr::new("10").into()],
+        };
+        let options = ToolOptions {
+            keep: None,
+            filter: crate::filter::Filter::default(),
+            iterations: 1,
+            warmup: 0,
+            include_kernel: false,
+            region_of_interest: false,
+            per_thread: false,
+            blocks: None,
+            inline_frames: false,
+            timeout: Some(Duration::from_secs(2)),
+        };
+        let start = Instant::now();
+        let output = super::run_ptrace(request, &options).unwrap();
+        assert_eq!(output.exit(), Exit::TimedOut);
+        assert!(
+            start.elapsed() < Duration::from_secs(8),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+}
@@ -1,25 +1,12 @@
+use crate::filter::Filter;
 use crate::{Entry, Statistics};
 
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
+use std::process::Command;
 
-#[derive(Default)]
-struct Item {
-    total: u32,
-    mem_read: u32,
-    mem_write: u32,
-    stack_read: u32,
-    stack_write: u32,
-    call: u32,
-    branch: u32,
-    r#return: u32,
-    compare: u32,
-    logic: u32,
-    arithmetic: u32,
-    others: HashMap<String, u32>,
-}
+pub const TEMP_FILE: &str = "qbdi-out.bin";
 
+#[allow(clippy::result_unit_err)]
 pub fn run_qbdi(
     request: crate::CommandRequest,
     options: &crate::ToolOptions,
@@ -61,142 +48,341 @@ pub fn run_qbdi(
         {
             let library = super::adjacent_qbdi_lib(true).unwrap();
             command.env("LD_BIND_NOW", "1");
-            command.env("LD_PRELOAD", dbg!(library.display().to_string()));
+            command.env("LD_PRELOAD", library.display().to_string());
         }
 
         command.args(request.arguments);
         command
     };
 
-    command.stdout(Stdio::piped());
-
-    let mut child = command.spawn().unwrap();
-
-    let content = BufReader::new(child.stdout.take().unwrap());
-
-    let mut total: Statistics = Statistics::default();
-    let mut internal: Item = Item::default();
-
-    // TODO this seems highly inefficient
-    let mut items: HashMap<String, Item> = HashMap::new();
+    if options.region_of_interest {
+        command.env("DEPICT_REGION_OF_INTEREST", "1");
+    }
 
-    for line in content.lines() {
-        let line = line.unwrap();
+    let file_path: &str = options.keep.as_deref().unwrap_or(TEMP_FILE);
+    // The program may change directory
+    let Ok(absolute) = std::path::absolute(file_path) else {
+        eprintln!("invalid results path {file_path:?}");
+        return Err(());
+    };
+    command.env("DEPICT_QBDI_OUTPUT", absolute);
+    // Set by the tracer to the benchmarked process, so processes it starts do not write results
+    command.env_remove("DEPICT_QBDI_PID");
 
-        #[cfg(target_os = "linux")]
-        eprintln!("TEMP linux: {line}");
-
-        if let Some(rest) = line.strip_prefix("depict_qbdi::") {
-            let Some((func, rest)) = rest.split_once('/') else {
-                // TODO not sure why some items do not finish?
-                continue;
-            };
-
-            let Some((kind, count)) = rest.split_once('/') else {
-                dbg!(rest);
-                continue;
-            };
-            let Ok(count) = count.parse() else {
-                // TODO ...?
-                dbg!(kind, count, rest);
-                continue;
-            };
-
-            let func = format!("{func:#}", func = rustc_demangle::demangle(func));
-
-            let item: &mut Item = if options.merge_internals {
-                // TODO some other things are needed here
-                let bad_prefixes = &[
-                    "std::",
-                    "core::",
-                    "alloc::",
-                    "_",
-                    "*",
-                    "OUTLINED_FUNCTION_",
-                    // "<std::",
-                ];
-                let skip = bad_prefixes.iter().any(|prefix| func.starts_with(prefix));
-                if skip {
-                    &mut internal
-                } else {
-                    items.entry(func).or_default()
-                }
-            } else {
-                items.entry(func).or_default()
-            };
+    // The program's own output is passed through
+    let mut child = command.spawn().map_err(|error| {
+        eprintln!("could not run program: {error}");
+    })?;
+    let exit = super::wait(&mut child, options.timeout).map_err(|error| {
+        eprintln!("could not wait for program: {error}");
+    })?;
+    let Ok(content) = std::fs::read(file_path) else {
+        eprintln!("QBDI tracer did not write results to {file_path:?} (program {exit})");
+        return Err(());
+    };
+    if options.keep.is_none() {
+        let _ = std::fs::remove_file(file_path);
+    }
 
-            total.total += count;
-            item.total += count;
+    let mut counts = Counts::default();
+    // Paths and counts of regions, by id
+    let mut regions: Vec<(String, Counts)> = Vec::new();
+    // Calling context tree of parent and symbol name, with instructions executed in each
+    let mut contexts: Vec<(Option<usize>, String)> = Vec::new();
+    let mut weights: Vec<Statistics> = Vec::new();
+    // Number of calls and inclusive total by caller and callee
+    let mut calls: HashMap<(String, String), (u64, u64)> = HashMap::new();
+    let mut addresses = crate::debug_info::Addresses::new();
+    // With `--per-thread`. Each thread's records follow a `THREAD` record
+    let mut threads: Vec<(usize, Counts)> = Vec::new();
+    // Context ids are per thread, so they are offset by those of the previous threads
+    let mut context_offset = 0;
 
-            match kind {
-                "mem_read" => {
-                    item.mem_read = count;
-                    total.mem_read += count;
+    let mut records = Records(&content);
+    let mut read = || -> Option<()> {
+        while let Some(tag) = records.tag() {
+            match tag {
+                COUNT => {
+                    let (func, kind, count) = (records.str()?, records.str()?, records.count()?);
+                    counts.record(func, kind, count, &options.filter);
+                    if let Some((_, counts)) = threads.last_mut() {
+                        counts.record(func, kind, count, &options.filter);
+                    }
                 }
-                "mem_write" => {
-                    item.mem_write = count;
-                    total.mem_write += count;
+                THREAD => {
+                    let index = records.index()?;
+                    context_offset = contexts.len();
+                    if options.per_thread {
+                        threads.push((index, Counts::default()));
+                    }
                 }
-                "call" => {
-                    item.call = count;
-                    total.call += count;
+                INCLUSIVE => {
+                    let (func, kind, count) = (records.str()?, records.str()?, records.count()?);
+                    let func = super::demangle(func);
+                    counts.add_inclusive(&func, kind, count);
+                    if let Some((_, counts)) = threads.last_mut() {
+                        counts.add_inclusive(&func, kind, count);
+                    }
                 }
-                "return" => {
-                    item.r#return = count;
-                    total.r#return += count;
+                STACK => {
+                    // Parents are always written before their children
+                    let (_context, parent, count, func) = (
+                        records.index()?,
+                        records.parent()?,
+                        records.count()?,
+                        records.str()?,
+                    );
+                    contexts.push((
+                        parent.map(|parent| parent + context_offset),
+                        super::demangle(func),
+                    ));
+                    weights.push(Statistics {
+                        total: count,
+                        ..Statistics::default()
+                    });
                 }
-                "branch" => {
-                    item.branch = count;
-                    total.branch += count;
+                STACK_KIND => {
+                    let (context, kind, count) =
+                        (records.index()?, records.str()?, records.count()?);
+                    weights.get_mut(context + context_offset)?.set(kind, count);
                 }
-                "compare" => {
-                    item.compare = count;
-                    total.compare += count;
+                CALL => {
+                    let (caller, callee, count, inclusive) = (
+                        records.str()?,
+                        records.str()?,
+                        records.count()?,
+                        records.count()?,
+                    );
+                    let name = |func: &str| {
+                        let func = super::demangle(func);
+                        options.filter.name(&func).to_owned()
+                    };
+                    let call = calls.entry((name(caller), name(callee))).or_default();
+                    call.0 = crate::checked_add("call count", call.0, count);
+                    call.1 = crate::checked_add("inclusive", call.1, inclusive);
                 }
-                "logic" => {
-                    item.logic = count;
-                    total.logic += count;
+                ADDRESS => {
+                    let (offset, kind, count, func, image) = (
+                        records.u64()?,
+                        records.str()?,
+                        records.count()?,
+                        records.str()?,
+                        records.str()?,
+                    );
+                    let entry = addresses
+                        .entry((image.to_owned(), offset))
+                        .or_insert_with(|| (super::demangle(func), Statistics::default()));
+                    entry.1.add("total", count);
+                    entry.1.add(kind, count);
                 }
-                "arithmetic" => {
-                    item.arithmetic = count;
-                    total.arithmetic += count;
+                REGION => {
+                    // Parents are always written before their children
+                    let (_id, parent, name) = (records.index()?, records.parent()?, records.str()?);
+                    let path = match parent {
+                        Some(parent) => format!(
+                            "{parent}{separator}{name}",
+                            parent = regions.get(parent)?.0,
+                            separator = crate::REGION_SEPARATOR
+                        ),
+                        None => name.to_owned(),
+                    };
+                    regions.push((path, Counts::default()));
                 }
-                kind => {
-                    item.others.insert(kind.to_owned(), count);
-                    total.add_other(kind.to_owned(), count);
+                REGION_COUNT => {
+                    let (region, func, kind, count) = (
+                        records.index()?,
+                        records.str()?,
+                        records.str()?,
+                        records.count()?,
+                    );
+                    regions
+                        .get_mut(region)?
+                        .1
+                        .record(func, kind, count, &options.filter);
                 }
+                _ => return None,
             }
-        } else {
-            println!("{line}");
         }
+        Some(())
+    };
+    if read().is_none() {
+        eprintln!("QBDI tracer wrote malformed results to {file_path:?}");
+        return Err(());
     }
 
-    child.wait().unwrap();
+    let contexts: Vec<(Option<usize>, &str)> = contexts
+        .iter()
+        .map(|(parent, name)| (*parent, name.as_str()))
+        .collect();
+    let stacks = super::context_stacks(&contexts, &weights, &options.filter);
 
-    if options.merge_internals {
-        items.insert("Internal".to_owned(), internal);
+    let total = counts.total.clone();
+    let mut symbols = counts.into_entries(&options.filter);
+    // Only for the symbol table. The call stacks from the tracer are by symbol
+    if options.inline_frames {
+        crate::debug_info::attribute_inlined(&mut symbols, &mut addresses, &options.filter);
     }
-
-    let symbols: Vec<_> = items
+    let regions = regions
+        .into_iter()
+        .map(|(name, counts)| crate::Region {
+            name,
+            total: counts.total.clone(),
+            symbols: counts.into_entries(&options.filter),
+        })
+        .collect();
+    let threads = threads
         .into_iter()
-        .map(|(name, item)| Entry {
-            symbol_name: name,
-            statistics: Statistics {
-                total: item.total,
-                mem_read: item.mem_read,
-                mem_write: item.mem_write,
-                stack_read: item.stack_read,
-                stack_write: item.stack_write,
-                call: item.call,
-                r#return: item.r#return,
-                branch: item.branch,
-                compare: item.compare,
-                logic: item.logic,
-                arithmetic: item.arithmetic,
-                others: item.others,
-            },
+        .map(|(index, counts)| crate::Thread {
+            index,
+            total: counts.total.clone(),
+            symbols: counts.into_entries(&options.filter),
         })
         .collect();
 
-    Ok(crate::ToolOutput::SymbolInstructionCounts { total, symbols })
+    Ok(crate::ToolOutput::SymbolInstructionCounts {
+        total,
+        symbols,
+        regions,
+        calls: calls
+            .into_iter()
+            .map(|((caller, callee), (count, inclusive))| crate::Call {
+                caller,
+                callee,
+                count,
+                inclusive: Statistics {
+                    total: inclusive,
+                    ..Statistics::default()
+                },
+            })
+            .collect(),
+        stacks,
+        lines: crate::debug_info::lines(addresses),
+        threads,
+        exit,
+    })
+}
+
+/// Record tags, matching `qbdi/icount.c`
+const COUNT: u8 = 1;
+const THREAD: u8 = 2;
+const INCLUSIVE: u8 = 3;
+const STACK: u8 = 4;
+const STACK_KIND: u8 = 5;
+const CALL: u8 = 6;
+const ADDRESS: u8 = 7;
+const REGION: u8 = 8;
+const REGION_COUNT: u8 = 9;
+
+/// Records written by the tracer: a tag byte followed by its fields. Numbers are little endian
+/// `u64`s (`u64::MAX` for no parent) and strings are prefixed with their `u32` length
+struct Records<'a>(&'a [u8]);
+
+impl<'a> Records<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn tag(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn count(&mut self) -> Option<u64> {
+        self.u64()
+    }
+
+    fn index(&mut self) -> Option<usize> {
+        self.u64()?.try_into().ok()
+    }
+
+    fn parent(&mut self) -> Option<Option<usize>> {
+        match self.u64()? {
+            u64::MAX => Some(None),
+            parent => Some(Some(parent.try_into().ok()?)),
+        }
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let length = u32::from_le_bytes(self.take(4)?.try_into().ok()?);
+        std::str::from_utf8(self.take(length as usize)?).ok()
+    }
+}
+
+/// Counts for the whole program or a region
+#[derive(Default)]
+struct Counts {
+    total: Statistics,
+    // TODO this seems highly inefficient
+    items: HashMap<String, Statistics>,
+    /// By (demangled) symbol name. Only for the whole program
+    inclusive: HashMap<String, Statistics>,
+}
+
+impl Counts {
+    fn record(&mut self, func: &str, kind: &str, count: u64, filter: &Filter) {
+        let func = super::demangle(func);
+
+        let item: &mut Statistics = match filter.bucket(&func) {
+            Some(bucket) => self.items.entry(bucket.to_owned()).or_default(),
+            None => self.items.entry(func).or_default(),
+        };
+        item.add("total", count);
+        item.add(kind, count);
+        self.total.add("total", count);
+        self.total.add(kind, count);
+    }
+
+    /// Threads have separate inclusive counts, which are summed
+    fn add_inclusive(&mut self, func: &str, kind: &str, count: u64) {
+        self.inclusive
+            .entry(func.to_owned())
+            .or_default()
+            .add(kind, count);
+    }
+
+    fn into_entries(mut self, filter: &Filter) -> Vec<Entry> {
+        let tracks_calls = !self.inclusive.is_empty();
+
+        self.items
+            .into_iter()
+            .map(|(name, item)| Entry {
+                // Functions only entered by jumps are never on the call stack, so at least have
+                // their own counts
+                inclusive: (tracks_calls && !filter.is_bucket(&name))
+                    .then(|| self.inclusive.remove(&name).unwrap_or_else(|| item.clone())),
+                symbol_name: name,
+                statistics: item,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Records;
+
+    #[test]
+    fn counts_above_u32() {
+        let value: u64 = (1 << 32) + 5;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(b"run");
+
+        let mut records = Records(&bytes);
+        assert_eq!(records.count(), Some(value));
+        assert_eq!(records.parent(), Some(None));
+        assert_eq!(records.str(), Some("run"));
+        // Truncated
+        assert_eq!(Records(&bytes[..4]).count(), None);
+    }
 }
@@ -1,28 +1,49 @@
+pub use sde_output_parser::Block;
+
 use std::io::BufReader;
 use std::process::{Command, Stdio};
-use wait_timeout::ChildExt;
 
 pub const TEMP_FILE: &str = "sde-out.txt";
 
+/// Without `--timeout`. SDE is slow enough that a hung program is easy to mistake for a long run
+pub const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_mins(3);
+
+/// Adjacent to the binary, under `SDE_PATH` or on `PATH`
+#[must_use]
+pub fn find_sde() -> Option<String> {
+    if let Some(path) = super::adjacent_sde_path(false)
+        && let Ok(true) = std::fs::exists(&path)
+    {
+        Some(path.into_os_string().into_string().unwrap())
+    } else if let Ok(dir) = std::env::var("SDE_PATH") {
+        Some(format!("{dir}/sde"))
+    } else {
+        let name = if cfg!(target_os = "windows") {
+            "sde.exe"
+        } else {
+            "sde"
+        };
+        std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(name))
+                .find(|path| path.is_file())
+                .map(|_| String::from("sde"))
+        })
+    }
+}
+
+#[allow(clippy::result_unit_err)]
 pub fn run_sde(
     request: crate::CommandRequest,
     options: &crate::ToolOptions,
 ) -> Result<crate::ToolOutput, ()> {
     let file_path: &str = options.keep.as_deref().unwrap_or(TEMP_FILE);
 
-    // TODO hmm
-    let blocks = 30;
+    // Also gives the addresses for lines
+    let blocks = options.blocks.unwrap_or(30);
 
-    {
-        let sde_path = if let Some(path) = super::adjacent_sde_path(true)
-            && let Ok(true) = std::fs::exists(&path)
-        {
-            path.into_os_string().into_string().unwrap()
-        } else if let Ok(dir) = std::env::var("SDE_PATH") {
-            format!("{dir}/sde")
-        } else {
-            String::from("sde")
-        };
+    let exit = {
+        let sde_path = find_sde().unwrap_or_else(|| String::from("sde"));
 
         let mut command = Command::new(sde_path);
         command.args([
@@ -31,56 +52,69 @@ pub fn run_sde(
             "-mix_filter_no_shared_libs",
             "-top_blocks",
             &blocks.to_string(),
-            "--",
         ]);
+        if options.region_of_interest {
+            command.args([
+                "-start_ssc_mark",
+                &format!("{:x}:repeat", crate::START_SSC_MARK),
+                "-stop_ssc_mark",
+                &format!("{:x}:repeat", crate::STOP_SSC_MARK),
+            ]);
+        }
+        command.arg("--");
         command.arg(request.program);
         command.args(request.arguments);
         command.stdout(Stdio::inherit());
         command.stderr(Stdio::inherit());
 
-        let mut child = command.spawn().unwrap();
-        let secs = std::time::Duration::from_mins(3);
-        let _status_code = if let Some(status) = child.wait_timeout(secs).unwrap() {
-            status.code()
+        let mut child = command.spawn().map_err(|error| {
+            eprintln!("could not run SDE: {error}");
+        })?;
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        super::wait(&mut child, Some(timeout)).map_err(|error| {
+            eprintln!("could not wait for SDE: {error}");
+        })?
+    };
+
+    let Ok(content) = std::fs::read(file_path) else {
+        eprintln!("SDE did not write results to {file_path:?} (it {exit})");
+        return Err(());
+    };
+
+    let rows = sde_output_parser::parse(BufReader::new(content.as_slice()));
+
+    let mut hot_blocks = sde_output_parser::parse_blocks(BufReader::new(content.as_slice()));
+    for block in &mut hot_blocks {
+        block.function = super::demangle(&block.function);
+    }
+
+    // Only the hottest blocks have addresses, so lines are partial
+    let mut addresses = crate::debug_info::Addresses::new();
+    for block in &hot_blocks {
+        let instructions: Vec<(u64, u64)> = if block.disassembly.is_empty() {
+            // Without the disassembly, the block is attributed to its first instruction
+            vec![(block.offset, block.instructions)]
         } else {
-            dbg!("sde timed out, killing");
-            child.kill().unwrap();
-            child.wait().unwrap().code()
+            block
+                .disassembly
+                .iter()
+                .map(|(address, _)| {
+                    (
+                        block.offset + address.saturating_sub(block.address),
+                        block.executions,
+                    )
+                })
+                .collect()
         };
+        for (offset, count) in instructions {
+            let entry = addresses
+                .entry((block.image.clone(), offset))
+                .or_insert_with(|| (block.function.clone(), crate::Statistics::default()));
+            entry.1.add("total", count);
+        }
     }
 
-    let file = std::fs::File::open(file_path).expect("sde did not create file");
-
-    let out = BufReader::new(file);
-
-    // TODO want options.merge_internals, not skip internals
-    let rows = sde_output_parser::parse(out, false);
-
-    let symbols: Vec<_> = rows
-        .into_iter()
-        .map(|(symbol_name, item)| crate::Entry {
-            symbol_name,
-            statistics: crate::Statistics {
-                total: item.total,
-                mem_read: item.mem_read,
-                mem_write: item.mem_write,
-                stack_read: item.stack_read,
-                stack_write: item.stack_write,
-                call: item.call,
-                // TODO
-                compare: 0,
-                // TODO
-                arithmetic: 0,
-                // TODO
-                logic: 0,
-                // TODO
-                r#return: 0,
-                // TODO
-                branch: 0,
-                others: std::collections::HashMap::default(),
-            },
-        })
-        .collect();
+    let symbols = entries(rows, &options.filter);
 
     let total: crate::Statistics =
         symbols
@@ -94,5 +128,92 @@ pub fn run_sde(
         std::fs::remove_file(file_path).unwrap();
     }
 
-    Ok(crate::ToolOutput::SymbolInstructionCounts { total, symbols })
+    let threads: Vec<crate::Thread> = if options.per_thread {
+        sde_output_parser::parse_threads(BufReader::new(content.as_slice()))
+            .into_iter()
+            .map(|thread| crate::Thread {
+                index: thread.tid,
+                symbols: entries(thread.functions, &options.filter),
+                total: statistics(thread.total),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if !threads.is_empty() && threads.iter().all(|thread| thread.symbols.is_empty()) {
+        eprintln!("SDE did not write counts by function for each thread (needed for --per-thread)");
+        return Err(());
+    }
+
+    if options.blocks.is_some() {
+        return Ok(crate::ToolOutput::HotBlocks {
+            blocks: hot_blocks,
+            total: total.total,
+            exit,
+        });
+    }
+
+    Ok(crate::ToolOutput::SymbolInstructionCounts {
+        total,
+        symbols,
+        regions: Vec::new(),
+        calls: Vec::new(),
+        stacks: Vec::new(),
+        lines: crate::debug_info::lines(addresses),
+        threads,
+        exit,
+    })
+}
+
+fn entries(
+    rows: Vec<(String, sde_output_parser::Count)>,
+    filter: &crate::filter::Filter,
+) -> Vec<crate::Entry> {
+    let mut symbols: Vec<crate::Entry> = Vec::with_capacity(rows.len());
+    for (symbol_name, item) in rows {
+        let symbol_name = super::demangle(&symbol_name);
+        let symbol_name = filter.name(&symbol_name);
+        let statistics = statistics(item);
+        match symbols
+            .iter_mut()
+            .find(|entry| entry.symbol_name == symbol_name)
+        {
+            Some(entry) => entry.statistics += statistics,
+            None => symbols.push(crate::Entry {
+                symbol_name: symbol_name.to_owned(),
+                statistics,
+                // The mix output does not have call stacks
+                inclusive: None,
+            }),
+        }
+    }
+    symbols
+}
+
+fn statistics(count: sde_output_parser::Count) -> crate::Statistics {
+    let mut statistics = crate::Statistics {
+        total: count.total,
+        ..crate::Statistics::default()
+    };
+    for (name, value) in [
+        ("mem_read", count.mem_read),
+        ("mem_write", count.mem_write),
+        ("stack_read", count.stack_read),
+        ("stack_write", count.stack_write),
+        ("call", count.call),
+        ("branch", count.branch),
+        ("return", count.r#return),
+        ("compare", count.compare),
+        ("logic", count.logic),
+        ("arithmetic", count.arithmetic),
+    ] {
+        statistics.set(name, value);
+    }
+    for (category, value) in &count.others {
+        statistics.set(category, *value);
+    }
+    for (extension, value) in &count.isa_extensions {
+        statistics.set(&format!("isa_{extension}"), *value);
+    }
+    statistics
 }
@@ -1 +1,186 @@
-// TODO
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Summary of repeated wall-clock runs. Everything is in terms of the *measured* runs
+/// (warm-up runs are discarded)
+#[derive(Clone, Debug)]
+pub struct TimingStatistics {
+    pub min: Duration,
+    pub max: Duration,
+    pub median: Duration,
+    pub mean: Duration,
+    pub standard_deviation: Duration,
+    /// Median absolute deviation (unscaled)
+    pub median_absolute_deviation: Duration,
+    /// 95% confidence interval of the mean (Student's t)
+    pub confidence_interval: (Duration, Duration),
+    /// Indexes into the runs which have a modified z-score above [`OUTLIER_THRESHOLD`]
+    pub outliers: Vec<usize>,
+}
+
+/// From Iglewicz and Hoaglin. Modified z-scores above this are considered outliers
+pub const OUTLIER_THRESHOLD: f64 = 3.5;
+
+/// Makes MAD a consistent estimator of the standard deviation for normally distributed data
+const MAD_SCALE: f64 = 1.4826;
+
+impl TimingStatistics {
+    /// `samples` must be non-empty
+    #[must_use]
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        assert!(!samples.is_empty(), "no samples to summarise");
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+
+        let min = sorted[0];
+        let max = sorted[sorted.len() - 1];
+        let median = median_of_sorted(&sorted);
+
+        let as_secs: Vec<f64> = samples.iter().map(Duration::as_secs_f64).collect();
+        let count = as_secs.len() as f64;
+        let mean = as_secs.iter().sum::<f64>() / count;
+        let variance = if as_secs.len() > 1 {
+            as_secs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.)
+        } else {
+            0.
+        };
+        let standard_deviation = variance.sqrt();
+
+        let mut deviations: Vec<Duration> = samples.iter().map(|x| x.abs_diff(median)).collect();
+        deviations.sort_unstable();
+        let median_absolute_deviation = median_of_sorted(&deviations);
+
+        let margin =
+            t_critical_95(as_secs.len().saturating_sub(1)) * standard_deviation / count.sqrt();
+        let confidence_interval = (
+            Duration::from_secs_f64((mean - margin).max(0.)),
+            Duration::from_secs_f64(mean + margin),
+        );
+
+        let scaled_mad = median_absolute_deviation.as_secs_f64() * MAD_SCALE;
+        let outliers = if scaled_mad > 0. {
+            let median = median.as_secs_f64();
+            as_secs
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| (*x - median).abs() / scaled_mad > OUTLIER_THRESHOLD)
+                .map(|(idx, _)| idx)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            min,
+            max,
+            median,
+            mean: Duration::from_secs_f64(mean),
+            standard_deviation: Duration::from_secs_f64(standard_deviation),
+            median_absolute_deviation,
+            confidence_interval,
+            outliers,
+        }
+    }
+
+    #[must_use]
+    pub fn as_rows(&self) -> [(&str, Duration); 8] {
+        [
+            ("min", self.min),
+            ("max", self.max),
+            ("median", self.median),
+            ("mean", self.mean),
+            ("stddev", self.standard_deviation),
+            ("mad", self.median_absolute_deviation),
+            ("ci_low", self.confidence_interval.0),
+            ("ci_high", self.confidence_interval.1),
+        ]
+    }
+}
+
+fn median_of_sorted(sorted: &[Duration]) -> Duration {
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Two-sided 95% critical values of Student's t-distribution
+fn t_critical_95(degrees_of_freedom: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    match degrees_of_freedom {
+        0 => 0.,
+        n @ 1..=30 => TABLE[n - 1],
+        31..=60 => 2.000,
+        61..=120 => 1.980,
+        _ => 1.960,
+    }
+}
+
+/// Runs the program `options.warmup` times (discarded) then `options.iterations` times,
+/// timing each run. The program's output is discarded. Stops at the first run which fails
+#[allow(clippy::result_unit_err)]
+pub fn run(
+    request: crate::CommandRequest,
+    options: &crate::ToolOptions,
+) -> Result<crate::ToolOutput, ()> {
+    if options.iterations == 0 {
+        eprintln!("time requires at least one run");
+        return Err(());
+    }
+
+    let mut command = Command::new(request.program);
+    command.args(request.arguments);
+    command.stdin(Stdio::null());
+    command.stdout(Stdio::null());
+    command.stderr(Stdio::null());
+
+    let mut time_run = || -> Result<(Duration, crate::Exit), ()> {
+        let start = Instant::now();
+        let exit = command
+            .spawn()
+            .and_then(|mut child| super::wait(&mut child, options.timeout));
+        let elapsed = start.elapsed();
+        match exit {
+            Ok(exit) => Ok((elapsed, exit)),
+            Err(error) => {
+                eprintln!("could not run program: {error}");
+                Err(())
+            }
+        }
+    };
+
+    let mut runs = Vec::with_capacity(options.iterations);
+    let mut exit = crate::Exit::SUCCESS;
+    for idx in 0..options.warmup + options.iterations {
+        let (elapsed, run_exit) = time_run()?;
+        if !run_exit.success() {
+            // Later runs would most likely fail the same way
+            exit = run_exit;
+            break;
+        }
+        if idx >= options.warmup {
+            runs.push(elapsed);
+        }
+    }
+
+    if runs.is_empty() {
+        eprintln!("benchmark {exit} before any measured run");
+        return Err(());
+    }
+
+    let statistics = TimingStatistics::from_samples(&runs);
+
+    Ok(crate::ToolOutput::Timings {
+        runs,
+        warmup: options.warmup,
+        statistics,
+        exit,
+    })
+}
@@ -23,23 +23,132 @@ impl Direction {
 
 /// Used for printing numbers in SDE
 #[must_use]
-pub fn count_with_seperator(value: usize) -> String {
+pub fn count_with_seperator(value: u64) -> String {
     const NON_BREAKING_SPACE: &str = "\u{00A0}";
 
     to_denary(value, NON_BREAKING_SPACE)
 }
 
-fn to_denary(value: usize, seperator: &str) -> String {
+fn to_denary(value: u64, seperator: &str) -> String {
     if value == 0 {
         return "0".to_owned();
     }
     let mut buf = String::new();
     for i in (0..=value.ilog10()).rev() {
-        let j = (value / 10i32.pow(i) as usize) % 10;
-        buf.push(b"0123456789"[j] as char);
+        let j = (value / 10u64.pow(i)) % 10;
+        buf.push(b"0123456789"[j as usize] as char);
         if i > 0 && i % 3 == 0 {
             buf.push_str(seperator);
         }
     }
     buf
 }
+
+/// Every combination of parameter values, in order (the last parameter varies fastest)
+#[must_use]
+pub fn cartesian_product(parameters: &[(String, Vec<String>)]) -> Vec<Vec<&str>> {
+    let mut combinations: Vec<Vec<&str>> = vec![Vec::new()];
+    for (_, values) in parameters {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|prefix| {
+                values.iter().map(move |value| {
+                    let mut combination = prefix.clone();
+                    combination.push(value.as_str());
+                    combination
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+/// Replaces `{name}` with the value of that parameter. Values are not substituted into, so a
+/// value containing `{other}` is kept as is
+#[must_use]
+pub fn substitute_parameters(
+    on: &str,
+    parameters: &[(String, Vec<String>)],
+    values: &[&str],
+) -> String {
+    let mut result = String::with_capacity(on.len());
+    let mut rest = on;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            parameters
+                .iter()
+                .zip(values)
+                .find(|((parameter, _), _)| parameter == name)
+                .map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                result.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Seconds, optionally with a unit: `90`, `1.5s`, `500ms` or `10m`
+///
+/// # Errors
+/// If the number or unit is invalid
+pub fn parse_duration(on: &str) -> Result<std::time::Duration, String> {
+    let split = on
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(on.len());
+    let (value, unit) = on.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration {on:?}"))?;
+    let seconds = match unit {
+        "" | "s" => value,
+        "ms" => value / 1000.,
+        "m" => value * 60.,
+        unit => return Err(format!("unknown unit {unit:?} in duration {on:?}")),
+    };
+    std::time::Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration {on:?}"))
+}
+
+/// Already serialised JSON, for nesting objects with [`json_builder_macro::Builder`]
+pub struct RawJSON(pub String);
+
+impl json_builder_macro::ToJSON for RawJSON {
+    fn append_as_json_string(&self, buf: &mut String) {
+        buf.push_str(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::substitute_parameters;
+
+    #[test]
+    fn substitutes_parameters_once() {
+        let parameters = [("a".to_owned(), Vec::new()), ("b".to_owned(), Vec::new())];
+        assert_eq!(
+            substitute_parameters("-n {a} -m {b}", &parameters, &["1", "2"]),
+            "-n 1 -m 2"
+        );
+        // Not substituted into values
+        assert_eq!(
+            substitute_parameters("{a}-{b}", &parameters, &["{b}", "2"]),
+            "{b}-2"
+        );
+        // Unknown and unclosed placeholders are kept
+        assert_eq!(
+            substitute_parameters("{c} {{a}} {a", &parameters, &["1", "2"]),
+            "{c} {1} {a"
+        );
+    }
+}